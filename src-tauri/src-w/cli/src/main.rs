@@ -0,0 +1,367 @@
+mod output;
+
+use crate::output::{
+    print_deploy_off, print_drift, print_entries, print_entry, print_progress, print_reports,
+};
+use anyhow::Context;
+use clap::{Parser, Subcommand, ValueEnum};
+use m_core::data::deploy::{DeployMethod, DeployOffMode};
+use m_core::data::duplicate::DuplicatePolicy;
+use m_core::data::library::{
+    lib_add_with, lib_check_deployments, lib_del, lib_delegate_create, lib_delegate_deploy,
+    lib_delegate_deploy_off, lib_delegate_repair, lib_export, lib_fresh, lib_get, lib_get_all,
+    lib_import, lib_index_rebuild, lib_set_passphrase, lib_verify, lib_verify_all,
+};
+use m_core::data::metadata::{Metadata, Platform};
+use m_core::foundation::config;
+use m_core::foundation::secret::{secret_is_locked, secret_unlock};
+use m_core::util::flate::{ArchiveOptions, CancelToken, CompressFormat, FlateMonitor};
+use std::ops::ControlFlow;
+use tracing::Level;
+
+/// Headless access to the meta-manager library
+#[derive(Debug, Parser)]
+#[command(name = "meta-cli", version, about)]
+struct Cli {
+    /// Print results as JSON instead of a table
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Print debug logs to stderr
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List all entries in the library
+    List,
+
+    /// Show a single entry
+    Get { id: String },
+
+    /// Add an entry for an existing archive or directory
+    Add {
+        #[arg(long)]
+        title: String,
+        #[arg(long)]
+        archive_path: String,
+        /// Steam, DLSite, Unknown, or any other name
+        #[arg(long, default_value = "Unknown", value_parser = parse_platform)]
+        platform: Platform,
+        #[arg(long)]
+        platform_id: Option<String>,
+        /// What to do if the entry duplicates an existing one
+        #[arg(long, value_enum, default_value_t = OnDuplicate::Reject)]
+        on_duplicate: OnDuplicate,
+    },
+
+    /// Delete an entry from the library
+    Del { id: String },
+
+    /// Compress a directory into the archive folder and add it as an entry
+    Create {
+        #[arg(long)]
+        title: String,
+        #[arg(long)]
+        from_path: String,
+        /// Steam, DLSite, Unknown, or any other name
+        #[arg(long, default_value = "Unknown", value_parser = parse_platform)]
+        platform: Platform,
+        #[arg(long)]
+        platform_id: Option<String>,
+        /// Archive format to create, only 7z and zip can have a password
+        #[arg(long, value_enum, default_value_t = Format::SevenZ)]
+        format: Format,
+        /// From 0 to store only up to 9
+        #[arg(long, default_value_t = 9, value_parser = clap::value_parser!(u32).range(0..=9))]
+        level: u32,
+        /// Compress each file of a 7z on its own
+        #[arg(long)]
+        no_solid: bool,
+        #[arg(long)]
+        password: Option<String>,
+        /// Encrypt the file names of a 7z as well, needs a password
+        #[arg(long, requires = "password")]
+        encrypt_header: bool,
+        /// What to do if the entry duplicates an existing one
+        #[arg(long, value_enum, default_value_t = OnDuplicate::Reject)]
+        on_duplicate: OnDuplicate,
+    },
+
+    /// Deploy an entry to the given directory
+    Deploy {
+        id: String,
+        path: String,
+        /// How a directory archive is placed, archive files are always extracted
+        #[arg(long, value_enum, default_value_t = Method::Copy)]
+        method: Method,
+    },
+
+    /// Remove the deployed files of an entry, files added since are left alone
+    DeployOff {
+        id: String,
+        /// Id of a single deployment, all deployments if not given
+        #[arg(long)]
+        deployment: Option<String>,
+        /// Keep deployed files that have been modified since, like saves or configs
+        #[arg(long)]
+        keep_user_files: bool,
+    },
+
+    /// Export the library to JSON in the data directory
+    Export,
+
+    /// Import the library from JSON in the data directory
+    Import,
+
+    /// Rebuild the secondary indexes of the library
+    Reindex,
+
+    /// Re-hash archives and report missing, resized or corrupted ones, all entries if no id is given
+    Verify { id: Option<String> },
+
+    /// Report every deployment that is missing, partially deleted or modified since deployment
+    Check,
+
+    /// Restore the missing or modified files of a deployment from the archive
+    Repair { id: String, deployment: String },
+
+    /// Encrypt archive passwords with a new master passphrase read from META_NEW_PASSPHRASE,
+    /// kept off the command line like the one in META_PASSPHRASE unlocking them
+    SetPassphrase {
+        /// Remove the master passphrase and use a key file in the data directory instead
+        #[arg(long)]
+        remove: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OnDuplicate {
+    Reject,
+    Force,
+    Merge,
+}
+
+impl From<OnDuplicate> for DuplicatePolicy {
+    fn from(value: OnDuplicate) -> Self {
+        match value {
+            OnDuplicate::Reject => DuplicatePolicy::Reject,
+            OnDuplicate::Force => DuplicatePolicy::Force,
+            OnDuplicate::Merge => DuplicatePolicy::Merge,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    #[value(name = "7z")]
+    SevenZ,
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+    TarXz,
+}
+
+impl From<Format> for CompressFormat {
+    fn from(value: Format) -> Self {
+        match value {
+            Format::SevenZ => CompressFormat::SevenZ,
+            Format::Zip => CompressFormat::Zip,
+            Format::Tar => CompressFormat::Tar,
+            Format::TarGz => CompressFormat::TarGz,
+            Format::TarZst => CompressFormat::TarZst,
+            Format::TarXz => CompressFormat::TarXz,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Method {
+    Copy,
+    Symlink,
+    Hardlink,
+    Reflink,
+}
+
+impl From<Method> for DeployMethod {
+    fn from(value: Method) -> Self {
+        match value {
+            Method::Copy => DeployMethod::Copy,
+            Method::Symlink => DeployMethod::Symlink,
+            Method::Hardlink => DeployMethod::Hardlink,
+            Method::Reflink => DeployMethod::Reflink,
+        }
+    }
+}
+
+fn parse_platform(s: &str) -> Result<Platform, String> {
+    Ok(match s.to_lowercase().as_str() {
+        "" | "unknown" => Platform::Unknown,
+        "steam" => Platform::Steam,
+        "dlsite" => Platform::DLSite,
+        _ => Platform::Other(s.to_string()),
+    })
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    tracing_subscriber::fmt()
+        .with_max_level(if cli.verbose {
+            Level::DEBUG
+        } else {
+            Level::WARN
+        })
+        .with_writer(std::io::stderr)
+        .init();
+
+    config::init_once_only().context("Failed to initialize config")?;
+    // Only taken from the environment, arguments are visible in the process list
+    if let Ok(passphrase) = std::env::var("META_PASSPHRASE")
+        && secret_is_locked()?
+    {
+        secret_unlock(&passphrase).context("Failed to unlock archive passwords")?;
+    }
+    lib_fresh().context("Failed to initialize library")?;
+
+    run(cli)
+}
+
+fn run(cli: Cli) -> anyhow::Result<()> {
+    match cli.command {
+        Command::List => {
+            let library = lib_get_all()?;
+            print_entries(library.entries(), cli.json)?;
+        }
+        Command::Get { id } => {
+            let metadata = lib_get(&id)?;
+            print_entry(&metadata, cli.json)?;
+        }
+        Command::Add {
+            title,
+            archive_path,
+            platform,
+            platform_id,
+            on_duplicate,
+        } => {
+            let mut metadata = Metadata::new(title, platform, platform_id, archive_path);
+            let _ = metadata.calculate_size();
+            let id = lib_add_with(metadata, on_duplicate.into())?;
+            print_entry(&lib_get(&id)?, cli.json)?;
+        }
+        Command::Del { id } => {
+            lib_get(&id)?;
+            lib_del(&id)?;
+            println!("Deleted {id}");
+        }
+        Command::Create {
+            title,
+            from_path,
+            platform,
+            platform_id,
+            format,
+            level,
+            no_solid,
+            password,
+            encrypt_header,
+            on_duplicate,
+        } => {
+            let options = ArchiveOptions {
+                format: format.into(),
+                level,
+                solid: !no_solid,
+                password,
+                encrypt_header,
+            };
+            let id = lib_delegate_create(
+                title,
+                platform,
+                platform_id,
+                from_path,
+                options,
+                on_duplicate.into(),
+                &FlateMonitor::new(CancelToken::new(), print_progress),
+            )?;
+            print_entry(&lib_get(&id)?, cli.json)?;
+        }
+        Command::Deploy { id, path, method } => {
+            lib_delegate_deploy(
+                &id,
+                &path,
+                method.into(),
+                &FlateMonitor::new(CancelToken::new(), print_progress),
+            )?;
+            print_entry(&lib_get(&id)?, cli.json)?;
+        }
+        Command::DeployOff {
+            id,
+            deployment,
+            keep_user_files,
+        } => {
+            let mode = if keep_user_files {
+                DeployOffMode::KeepUserFiles
+            } else {
+                DeployOffMode::RemoveDeployed
+            };
+            let reports = lib_delegate_deploy_off(&id, deployment.as_deref(), mode)?;
+            print_deploy_off(&reports, cli.json)?;
+        }
+        Command::Export => {
+            lib_export()?;
+            println!("Library exported");
+        }
+        Command::Import => {
+            if lib_import()? {
+                println!("Library imported");
+            } else {
+                anyhow::bail!("No exported library file found in the data directory");
+            }
+        }
+        Command::Reindex => {
+            let count = lib_index_rebuild()?;
+            println!("Reindexed {count} entries");
+        }
+        Command::Verify { id } => {
+            let reports = match id {
+                Some(id) => vec![lib_verify(&id)?],
+                None => lib_verify_all(|progress| {
+                    eprintln!(
+                        "[{}/{}] {}",
+                        progress.current, progress.total, progress.title
+                    );
+                    ControlFlow::Continue(())
+                })?,
+            };
+            print_reports(&reports, cli.json)?;
+        }
+        Command::Check => {
+            print_drift(&lib_check_deployments()?, cli.json)?;
+        }
+        Command::Repair { id, deployment } => {
+            let report = lib_delegate_repair(
+                &id,
+                &deployment,
+                &FlateMonitor::new(CancelToken::new(), print_progress),
+            )?;
+            print_drift(&[report], cli.json)?;
+        }
+        Command::SetPassphrase { remove } => {
+            let new = if remove {
+                None
+            } else {
+                Some(
+                    std::env::var("META_NEW_PASSPHRASE")
+                        .context("Set META_NEW_PASSPHRASE, or pass --remove to use a key file")?,
+                )
+            };
+            let count = lib_set_passphrase(new.as_deref())?;
+            println!("Encrypted {count} passwords with the new key");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use m_core::data::deploy::{DeployOffReport, DriftStatus};
+use m_core::data::library::{DriftReport, VerifyReport};
+use m_core::data::metadata::{Metadata, VerifyStatus};
+use m_core::util::flate::FlateProgress;
+use std::io::{IsTerminal, Write};
+
+const TABLE_HEADER: [&str; 6] = ["ID", "TITLE", "PLATFORM", "PLATFORM ID", "SIZE", "DEPLOYED"];
+
+/// Print a list of entries either as a JSON array or as a table
+pub fn print_entries(entries: &[Metadata], json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(entries)?);
+    } else {
+        let rows = entries.iter().map(table_row).collect::<Vec<_>>();
+        print_table(&rows);
+    }
+    Ok(())
+}
+
+/// Print a single entry either as a JSON object or as a table
+pub fn print_entry(entry: &Metadata, json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(entry)?);
+    } else {
+        print_table(&[table_row(entry)]);
+    }
+    Ok(())
+}
+
+/// Print verification reports either as a JSON array or as one line per entry
+pub fn print_reports(reports: &[VerifyReport], json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(reports)?);
+        return Ok(());
+    }
+    for report in reports {
+        let status = match &report.status {
+            VerifyStatus::Intact => "intact".to_string(),
+            VerifyStatus::Unhashed(_) => "hash recorded".to_string(),
+            VerifyStatus::NoArchive => "no archive".to_string(),
+            VerifyStatus::Missing => "MISSING".to_string(),
+            VerifyStatus::Unreadable(err) => format!("UNREADABLE ({err})"),
+            VerifyStatus::UnknownFormat => "UNKNOWN FORMAT".to_string(),
+            VerifyStatus::MissingVolumes(missing) => {
+                format!("MISSING VOLUMES ({})", missing.join(", "))
+            }
+            VerifyStatus::Resized { expected, actual } => {
+                format!("RESIZED ({expected} -> {actual} bytes)")
+            }
+            VerifyStatus::Corrupted { .. } => "CORRUPTED".to_string(),
+        };
+        println!("{}  {}  {}", report.id, status, report.title);
+    }
+    Ok(())
+}
+
+/// Print deployment checks either as a JSON array or as one line per deployment
+pub fn print_drift(reports: &[DriftReport], json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(reports)?);
+        return Ok(());
+    }
+    for report in reports {
+        let status = match &report.status {
+            DriftStatus::Intact => "intact".to_string(),
+            DriftStatus::Missing => "MISSING".to_string(),
+            DriftStatus::Partial(drift) => format!(
+                "PARTIAL ({} missing, {} modified)",
+                drift.missing.len(),
+                drift.modified.len()
+            ),
+            DriftStatus::Modified(drift) => format!("MODIFIED ({} files)", drift.modified.len()),
+            DriftStatus::Unchecked => "unchecked".to_string(),
+            DriftStatus::Unreadable(err) => format!("UNREADABLE ({err})"),
+        };
+        println!(
+            "{}  {}  {}  {}  {}",
+            report.id, report.deployment, status, report.title, report.path
+        );
+    }
+    Ok(())
+}
+
+/// Print the outcome of deploying off either as a JSON array or as one line per kept or missing file,
+/// grouped by deployment
+pub fn print_deploy_off(reports: &[DeployOffReport], json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(reports)?);
+        return Ok(());
+    }
+    for report in reports {
+        println!("{}  {}", report.deployment, report.path);
+        for path in &report.modified {
+            let status = if report.removed.contains(path) {
+                "modified, removed"
+            } else {
+                "modified, kept"
+            };
+            println!("  {status}  {path}");
+        }
+        for path in &report.added {
+            println!("  added, kept  {path}");
+        }
+        for path in &report.missing {
+            println!("  missing  {path}");
+        }
+        println!("  Removed {} files", report.removed.len());
+    }
+    Ok(())
+}
+
+/// Redraw a progress line on stderr, nothing is printed if stderr is not a terminal
+pub fn print_progress(progress: &FlateProgress) {
+    let mut stderr = std::io::stderr();
+    if !stderr.is_terminal() {
+        return;
+    }
+    let bytes = match progress.bytes_total {
+        Some(total) if total > 0 => format!(
+            "{:>3}% of {}",
+            progress.bytes_done * 100 / total,
+            format_size(total)
+        ),
+        _ => format_size(progress.bytes_done),
+    };
+    let _ = match progress.current.as_deref() {
+        Some(current) => write!(stderr, "\r\x1b[2K{bytes}  {current}"),
+        None => writeln!(stderr, "\r\x1b[2K{bytes}"),
+    };
+    let _ = stderr.flush();
+}
+
+fn table_row(entry: &Metadata) -> [String; 6] {
+    [
+        entry.id.clone(),
+        entry.title.clone(),
+        entry.platform.to_string(),
+        entry.platform_id.clone().unwrap_or_default(),
+        entry.size_bytes.map(format_size).unwrap_or_default(),
+        entry
+            .deployments
+            .iter()
+            .map(|deployment| deployment.path.as_str())
+            .collect::<Vec<_>>()
+            .join(", "),
+    ]
+}
+
+fn print_table(rows: &[[String; 6]]) {
+    let mut widths = TABLE_HEADER.map(|h| h.chars().count());
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header = TABLE_HEADER.map(str::to_string);
+    for row in std::iter::once(&header).chain(rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} {}", UNITS[0])
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
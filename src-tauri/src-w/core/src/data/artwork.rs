@@ -0,0 +1,188 @@
+use crate::foundation::config::{ConfigError, get_data_dir};
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tracing::{info, warn};
+
+const ARTWORK_DIR_NAME: &str = "artwork";
+const THUMBNAIL_SUFFIX: &str = ".thumb.jpg";
+const THUMBNAIL_SIZE: u32 = 320;
+
+/// An image stored for an entry under `artwork/<entry id>/`, named by the BLAKE3 hex digest
+/// of its content, with a JPEG thumbnail next to it
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct Artwork {
+    pub hash: String,
+    pub ext: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Artwork {
+    pub fn file_name(&self) -> String {
+        format!("{}.{}", self.hash, self.ext)
+    }
+
+    pub fn thumbnail_name(&self) -> String {
+        format!("{}{THUMBNAIL_SUFFIX}", self.hash)
+    }
+
+    pub fn mime(&self) -> &'static str {
+        ImageFormat::from_extension(&self.ext)
+            .map(|format| format.to_mime_type())
+            .unwrap_or("application/octet-stream")
+    }
+}
+
+/// Directory holding the images of an entry
+pub fn artwork_dir(id: &str) -> Result<PathBuf, ArtworkError> {
+    Ok(get_data_dir()?.join(ARTWORK_DIR_NAME).join(id))
+}
+
+/// Store an image for an entry, the same content is only written once
+pub fn artwork_store(id: &str, data: &[u8]) -> Result<Artwork, ArtworkError> {
+    store_in(&artwork_dir(id)?, data)
+}
+
+/// Read a stored image of an entry, or its thumbnail
+pub fn artwork_read(id: &str, artwork: &Artwork, thumbnail: bool) -> Result<Vec<u8>, ArtworkError> {
+    let dir = artwork_dir(id)?;
+    let name = if thumbnail {
+        artwork.thumbnail_name()
+    } else {
+        artwork.file_name()
+    };
+    Ok(fs::read(dir.join(name))?)
+}
+
+/// Remove every image not referenced by the given entry ids and image hashes,
+/// returns the number of files removed
+pub fn artwork_gc(live: &HashMap<String, HashSet<String>>) -> Result<usize, ArtworkError> {
+    gc_in(&get_data_dir()?.join(ARTWORK_DIR_NAME), live)
+}
+
+fn store_in(dir: &Path, data: &[u8]) -> Result<Artwork, ArtworkError> {
+    let format = image::guess_format(data)?;
+    let image = image::load_from_memory_with_format(data, format)?;
+    let artwork = Artwork {
+        hash: blake3::hash(data).to_hex().to_string(),
+        ext: format
+            .extensions_str()
+            .first()
+            .copied()
+            .ok_or_else(|| ArtworkError::Unsupported(format!("{format:?}")))?
+            .to_string(),
+        width: image.width(),
+        height: image.height(),
+    };
+
+    fs::create_dir_all(dir)?;
+    let path = dir.join(artwork.file_name());
+    if !path.exists() {
+        fs::write(&path, data)?;
+    }
+    let thumbnail_path = dir.join(artwork.thumbnail_name());
+    if !thumbnail_path.exists() {
+        let mut thumbnail = Cursor::new(Vec::new());
+        DynamicImage::from(image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8())
+            .write_to(&mut thumbnail, ImageFormat::Jpeg)?;
+        fs::write(&thumbnail_path, thumbnail.into_inner())?;
+    }
+    Ok(artwork)
+}
+
+fn gc_in(root: &Path, live: &HashMap<String, HashSet<String>>) -> Result<usize, ArtworkError> {
+    if !root.exists() {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    for dir in fs::read_dir(root)? {
+        let dir = dir?.path();
+        let hashes = dir
+            .file_name()
+            .and_then(|id| live.get(id.to_string_lossy().as_ref()));
+        let Some(hashes) = hashes else {
+            removed += fs::read_dir(&dir)?.count();
+            info!("Removing images of deleted entry: {}", dir.display());
+            fs::remove_dir_all(&dir)?;
+            continue;
+        };
+        for file in fs::read_dir(&dir)? {
+            let file = file?.path();
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            let hash = name.split('.').next().unwrap_or_default();
+            if !hashes.contains(hash) {
+                if let Err(err) = fs::remove_file(&file) {
+                    warn!("Failed to remove image {}: {}", file.display(), err);
+                } else {
+                    removed += 1;
+                }
+            }
+        }
+    }
+    Ok(removed)
+}
+
+#[derive(Debug, Error)]
+pub enum ArtworkError {
+    #[error("FileSystem failure: {0}")]
+    FileError(#[from] io::Error),
+
+    #[error("Failed to decode or encode the image: {0}")]
+    ImageError(#[from] image::ImageError),
+
+    #[error("Not a supported image format: {0}")]
+    Unsupported(String),
+
+    #[error("Failed with config: {0}")]
+    ConfigError(#[from] ConfigError),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::file::cd_test_dir;
+    use image::RgbImage;
+
+    #[test]
+    fn test_store_and_gc() {
+        let root = cd_test_dir("test_artwork");
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::from(RgbImage::from_pixel(800, 400, image::Rgb([200, 40, 40])))
+            .write_to(&mut png, ImageFormat::Png)
+            .unwrap();
+        let png = png.into_inner();
+
+        let dir = root.join("entry");
+        let artwork = store_in(&dir, &png).unwrap();
+        assert_eq!(artwork.ext, "png");
+        assert_eq!((artwork.width, artwork.height), (800, 400));
+        assert_eq!(artwork.mime(), "image/png");
+        assert_eq!(store_in(&dir, &png).unwrap(), artwork);
+        assert_eq!(fs::read(dir.join(artwork.file_name())).unwrap(), png);
+        let thumbnail = image::open(dir.join(artwork.thumbnail_name())).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (320, 160));
+        assert!(store_in(&dir, b"not an image").is_err());
+
+        let orphan = store_in(&root.join("deleted"), &png).unwrap();
+        assert_eq!(orphan.hash, artwork.hash);
+        let live = HashMap::from([("entry".to_string(), HashSet::from([artwork.hash.clone()]))]);
+        assert_eq!(gc_in(&root, &live).unwrap(), 2);
+        assert!(!root.join("deleted").exists());
+        assert!(dir.join(artwork.thumbnail_name()).exists());
+
+        assert_eq!(
+            gc_in(
+                &root,
+                &HashMap::from([("entry".to_string(), HashSet::new())])
+            )
+            .unwrap(),
+            2
+        );
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::util::flate::ArchiveEntry;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::path::Path;
+
+const IMAGE_TYPES: [(&str, &str); 8] = [
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("png", "image/png"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("jxl", "image/jxl"),
+];
+
+/// What is known of a comic's pages, scanned from its archive contents
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct ComicInfo {
+    pub page_count: usize,
+    /// Path of the first page inside the archive
+    pub cover: String,
+}
+
+/// A page read out of a comic archive
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ComicPage {
+    pub name: String,
+    pub mime: &'static str,
+    pub data: Vec<u8>,
+}
+
+/// MIME type of an image file judged by its extension, [None] if it is not a page
+pub fn image_mime(path: &str) -> Option<&'static str> {
+    let ext = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+    IMAGE_TYPES
+        .iter()
+        .find(|(known, _)| *known == ext)
+        .map(|(_, mime)| *mime)
+}
+
+/// Compare like a reader would, runs of digits by their value: `2.jpg` before `10.jpg`
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_digits(&mut a);
+                let y = take_digits(&mut b);
+                let (x_trim, y_trim) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let order = x_trim
+                    .len()
+                    .cmp(&y_trim.len())
+                    .then_with(|| x_trim.cmp(y_trim))
+                    .then_with(|| x.len().cmp(&y.len()));
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let order = x.to_lowercase().cmp(y.to_lowercase());
+                if order != Ordering::Equal {
+                    return order;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    digits
+}
+
+/// The image files of an archive in reading order, macOS resource forks are left out
+pub fn sort_pages(entries: &[ArchiveEntry]) -> Vec<&str> {
+    let mut pages = entries
+        .iter()
+        .map(|entry| entry.path.as_str())
+        .filter(|path| {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            image_mime(path).is_some()
+                && !name.starts_with("._")
+                && !path.split('/').any(|part| part == "__MACOSX")
+        })
+        .collect::<Vec<_>>();
+    pages.sort_by(|a, b| natural_cmp(a, b));
+    pages
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(path: &str) -> ArchiveEntry {
+        ArchiveEntry {
+            path: path.to_string(),
+            size: 1,
+            compressed_size: None,
+            modified: None,
+            encrypted: false,
+        }
+    }
+
+    #[test]
+    fn test_natural_sort() {
+        assert_eq!(natural_cmp("page2.jpg", "page10.jpg"), Ordering::Less);
+        assert_eq!(natural_cmp("Page02.jpg", "page2.jpg"), Ordering::Greater);
+        assert_eq!(natural_cmp("a/10.png", "b/1.png"), Ordering::Less);
+        assert_eq!(image_mime("cover.JPG"), Some("image/jpeg"));
+        assert_eq!(image_mime("ComicInfo.xml"), None);
+
+        let entries = [
+            "vol1/10.jpg",
+            "vol1/2.jpg",
+            "__MACOSX/vol1/._1.jpg",
+            "vol1/._3.jpg",
+            "ComicInfo.xml",
+            "vol1/1.png",
+            "vol2/1.webp",
+        ]
+        .map(entry);
+        assert_eq!(
+            sort_pages(&entries),
+            vec!["vol1/1.png", "vol1/2.jpg", "vol1/10.jpg", "vol2/1.webp"]
+        );
+    }
+}
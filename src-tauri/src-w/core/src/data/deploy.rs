@@ -0,0 +1,364 @@
+use crate::data::metadata::DeployType;
+use crate::util::file;
+use crate::util::hash::hash_file;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// A single place an entry is deployed to, an entry may have several
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct Deployment {
+    pub id: String,
+    /// The target directory, or the copied file for [DeployType::CopyFile]
+    pub path: String,
+    pub deploy_type: DeployType,
+    pub date_deployed: DateTime<Utc>,
+    /// Files written by a directory deployment, [None] for ones made before manifests were recorded
+    #[serde(default)]
+    pub manifest: Option<DeployManifest>,
+}
+
+impl Deployment {
+    pub fn new(path: String, deploy_type: DeployType, manifest: Option<DeployManifest>) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            path,
+            deploy_type,
+            date_deployed: Utc::now(),
+            manifest,
+        }
+    }
+}
+
+/// How [Metadata::deploy](crate::data::metadata::Metadata::deploy) places a directory archive
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Default)]
+pub enum DeployMethod {
+    /// Copy or extract the files, the only method for archive files
+    #[default]
+    Copy,
+    /// Link the target to the archive directory, nothing is copied
+    Symlink,
+    /// Mirror the tree with hard links, which needs the archive and target on one filesystem.
+    /// Changing a deployed file in place changes the archive as well
+    Hardlink,
+    /// Mirror the tree with copy-on-write clones, where the filesystem supports it
+    Reflink,
+}
+
+/// How [Metadata::deploy_off](crate::data::metadata::Metadata::deploy_off) treats files changed since deployment
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Default)]
+pub enum DeployOffMode {
+    /// Remove every deployed file, including modified ones
+    #[default]
+    RemoveDeployed,
+    /// Keep deployed files that have been modified, like saves or configs
+    KeepUserFiles,
+}
+
+/// A file or a symlink written by a deployment
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct ManifestFile {
+    /// Relative to the deployed path, with `/` separators
+    pub path: String,
+    pub size: u64,
+    /// BLAKE3 hex digest, see [hash_file], empty for a symlink
+    pub hash: String,
+    /// Target of a symlink, kept as extracted from a tarball or a 7z
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+impl ManifestFile {
+    /// Whether the file under the root is gone, replaced or pointing elsewhere for a symlink
+    fn is_missing(&self, path: &Path) -> bool {
+        match &self.link {
+            Some(_) => !path.is_symlink(),
+            None => path.is_symlink() || !path.is_file(),
+        }
+    }
+
+    /// Whether the present file differs from the recorded one.
+    /// A same sized file is only hashed if `hash` is set
+    fn is_modified(&self, path: &Path, hash: bool) -> Result<bool, io::Error> {
+        match &self.link {
+            Some(link) => Ok(link_target(path)? != *link),
+            None => {
+                Ok(path.metadata()?.len() != self.size || (hash && hash_file(path)? != self.hash))
+            }
+        }
+    }
+}
+
+fn link_target(path: &Path) -> Result<String, io::Error> {
+    Ok(fs::read_link(path)?.to_string_lossy().to_string())
+}
+
+/// Files written by a deployment, so deploying off leaves anything else alone
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Default)]
+pub struct DeployManifest {
+    pub files: Vec<ManifestFile>,
+}
+
+/// Outcome of deploying off a single [Deployment], paths are relative to the deployed path
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Default)]
+pub struct DeployOffReport {
+    /// Id of the [Deployment]
+    pub deployment: String,
+    /// The deployed path, see [Deployment::path]
+    pub path: String,
+    pub removed: Vec<String>,
+    /// Deployed files changed since deployment, kept in [DeployOffMode::KeepUserFiles]
+    pub modified: Vec<String>,
+    /// Files not written by the deployment, never removed
+    pub added: Vec<String>,
+    /// Deployed files already gone
+    pub missing: Vec<String>,
+}
+
+/// Deployed files gone or changed since deployment, relative to the deployed path
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Default)]
+pub struct DeployDrift {
+    pub missing: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+/// State of a deployment compared with what was deployed,
+/// see [Metadata::check_deployment](crate::data::metadata::Metadata::check_deployment)
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub enum DriftStatus {
+    /// Every deployed file is present and unchanged
+    Intact,
+    /// The deployed path or every deployed file is gone
+    Missing,
+    /// Some deployed files are gone, others may be modified
+    Partial(DeployDrift),
+    /// Every deployed file is present, but some have changed
+    Modified(DeployDrift),
+    /// No manifest or archive hash recorded, only the deployed path is known to exist
+    Unchecked,
+    /// The deployment could not be read
+    Unreadable(String),
+}
+
+fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+impl DeployManifest {
+    /// Record every file under the directory
+    pub fn scan(root: impl AsRef<Path>) -> Result<Self, io::Error> {
+        let root = root.as_ref();
+        let mut files = Vec::new();
+        for entry in walkdir::WalkDir::new(root).sort_by_file_name() {
+            let entry = entry?;
+            if entry.file_type().is_symlink() {
+                files.push(ManifestFile {
+                    path: relative(root, entry.path()),
+                    size: 0,
+                    hash: String::new(),
+                    link: Some(link_target(entry.path())?),
+                });
+            } else if entry.file_type().is_file() {
+                files.push(ManifestFile {
+                    path: relative(root, entry.path()),
+                    size: entry.metadata()?.len(),
+                    hash: hash_file(entry.path())?,
+                    link: None,
+                });
+            }
+        }
+        info!(
+            "Recorded {} deployed files in {}",
+            files.len(),
+            root.display()
+        );
+        Ok(Self { files })
+    }
+
+    /// Compare the files under the root with the recorded ones, files added since are ignored
+    pub fn check(&self, root: impl AsRef<Path>) -> Result<DriftStatus, io::Error> {
+        let root = root.as_ref();
+        let mut drift = DeployDrift::default();
+        for file in &self.files {
+            let path = root.join(&file.path);
+            if file.is_missing(&path) {
+                drift.missing.push(file.path.clone());
+            } else if file.is_modified(&path, true)? {
+                drift.modified.push(file.path.clone());
+            }
+        }
+
+        Ok(
+            if !self.files.is_empty() && drift.missing.len() == self.files.len() {
+                DriftStatus::Missing
+            } else if !drift.missing.is_empty() {
+                DriftStatus::Partial(drift)
+            } else if !drift.modified.is_empty() {
+                DriftStatus::Modified(drift)
+            } else {
+                DriftStatus::Intact
+            },
+        )
+    }
+
+    /// Remove the recorded files under the root, then the directories they leave empty.
+    /// Files are only hashed to keep modified ones, with [DeployOffMode::RemoveDeployed]
+    /// only those of another size are reported as modified
+    pub fn remove(
+        &self,
+        root: impl AsRef<Path>,
+        mode: DeployOffMode,
+    ) -> Result<DeployOffReport, io::Error> {
+        let root = root.as_ref();
+        let mut report = DeployOffReport::default();
+        let mut dirs = HashSet::new();
+
+        for file in &self.files {
+            let path = root.join(&file.path);
+            if file.is_missing(&path) {
+                report.missing.push(file.path.clone());
+                continue;
+            }
+            if file.is_modified(&path, mode == DeployOffMode::KeepUserFiles)? {
+                report.modified.push(file.path.clone());
+                if mode == DeployOffMode::KeepUserFiles {
+                    continue;
+                }
+            }
+            if file.link.is_some() {
+                fs::remove_file(&path).or_else(|_| file::remove_symlink_dir(&path))?;
+            } else {
+                fs::remove_file(&path)?;
+            }
+            report.removed.push(file.path.clone());
+            dirs.extend(
+                path.ancestors()
+                    .skip(1)
+                    .take_while(|dir| *dir != root)
+                    .map(Path::to_path_buf),
+            );
+        }
+
+        // Deepest first, a directory still holding anything is kept
+        let mut dirs = dirs.into_iter().collect::<Vec<_>>();
+        dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        for dir in dirs {
+            let _ = fs::remove_dir(dir);
+        }
+
+        let recorded = self
+            .files
+            .iter()
+            .map(|f| f.path.as_str())
+            .collect::<HashSet<_>>();
+        for entry in walkdir::WalkDir::new(root).sort_by_file_name() {
+            let entry = entry?;
+            let path = relative(root, entry.path());
+            let file_type = entry.file_type();
+            if (file_type.is_file() || file_type.is_symlink()) && !recorded.contains(path.as_str())
+            {
+                report.added.push(path);
+            }
+        }
+
+        if !report.modified.is_empty() || !report.added.is_empty() {
+            warn!(
+                "Deployment at {} has {} modified and {} added files",
+                root.display(),
+                report.modified.len(),
+                report.added.len()
+            );
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::file::cd_test_dir;
+
+    #[test]
+    fn test_manifest_remove() {
+        let dir = cd_test_dir("manifest");
+        fs::create_dir_all(dir.join("save")).unwrap();
+        fs::create_dir_all(dir.join("data/sub")).unwrap();
+        fs::write(dir.join("game.exe"), b"exe").unwrap();
+        fs::write(dir.join("config.ini"), b"default").unwrap();
+        fs::write(dir.join("data/sub/a.bin"), b"a").unwrap();
+        fs::write(dir.join("gone.txt"), b"gone").unwrap();
+        let manifest = DeployManifest::scan(&dir).unwrap();
+        assert_eq!(manifest.files.len(), 4);
+
+        fs::write(dir.join("config.ini"), b"changed").unwrap();
+        fs::write(dir.join("save/1.sav"), b"save").unwrap();
+        fs::remove_file(dir.join("gone.txt")).unwrap();
+        assert_eq!(
+            manifest.check(&dir).unwrap(),
+            DriftStatus::Partial(DeployDrift {
+                missing: vec!["gone.txt".to_string()],
+                modified: vec!["config.ini".to_string()],
+            })
+        );
+
+        let report = manifest.remove(&dir, DeployOffMode::KeepUserFiles).unwrap();
+        assert_eq!(report.removed, vec!["data/sub/a.bin", "game.exe"]);
+        assert_eq!(report.modified, vec!["config.ini"]);
+        assert_eq!(report.added, vec!["save/1.sav"]);
+        assert_eq!(report.missing, vec!["gone.txt"]);
+        assert!(!dir.join("data").exists());
+        assert!(dir.join("config.ini").is_file());
+        assert!(dir.join("save/1.sav").is_file());
+
+        let report = manifest
+            .remove(&dir, DeployOffMode::RemoveDeployed)
+            .unwrap();
+        assert_eq!(report.removed, vec!["config.ini"]);
+        assert!(!dir.join("config.ini").exists());
+        assert!(dir.join("save/1.sav").is_file());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_manifest_symlink() {
+        let dir = cd_test_dir("manifest_symlink");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib/libfoo.so.1"), b"foo").unwrap();
+        file::symlink("libfoo.so.1", dir.join("lib/libfoo.so")).unwrap();
+        let manifest = DeployManifest::scan(&dir).unwrap();
+        let link = &manifest.files[0];
+        assert_eq!(link.path, "lib/libfoo.so");
+        assert_eq!(link.link.as_deref(), Some("libfoo.so.1"));
+        assert_eq!(manifest.check(&dir).unwrap(), DriftStatus::Intact);
+
+        fs::remove_file(dir.join("lib/libfoo.so")).unwrap();
+        file::symlink("elsewhere", dir.join("lib/libfoo.so")).unwrap();
+        assert_eq!(
+            manifest.check(&dir).unwrap(),
+            DriftStatus::Modified(DeployDrift {
+                missing: vec![],
+                modified: vec!["lib/libfoo.so".to_string()],
+            })
+        );
+
+        let report = manifest
+            .remove(&dir, DeployOffMode::RemoveDeployed)
+            .unwrap();
+        assert_eq!(report.removed, vec!["lib/libfoo.so", "lib/libfoo.so.1"]);
+        assert!(report.added.is_empty());
+        assert!(!dir.join("lib").exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
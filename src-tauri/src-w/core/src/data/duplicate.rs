@@ -0,0 +1,242 @@
+use crate::data::index::{ids_by_archive_path, ids_by_hash, ids_by_platform};
+use crate::data::library::{LIB_TABLE, LibraryError};
+use crate::data::metadata::Metadata;
+use crate::util::hash::hash_path;
+use redb::{ReadTransaction, ReadableTable};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// What to do when an entry being added duplicates existing ones
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Default)]
+pub enum DuplicatePolicy {
+    /// Fail with [LibraryError::Duplicate]
+    #[default]
+    Reject,
+    /// Add the entry anyway
+    Force,
+    /// Fill the first duplicate's missing fields from the new entry instead of adding it
+    Merge,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+pub enum DuplicateReason {
+    /// Same [Platform](crate::data::metadata::Platform) and platform id
+    PlatformId,
+    /// Same archive path
+    ArchivePath,
+    /// Different archive path, same archive content
+    ContentHash,
+}
+
+/// An existing entry found to duplicate a new one
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct Duplicate {
+    pub id: String,
+    pub title: String,
+    pub reasons: Vec<DuplicateReason>,
+}
+
+impl Display for Duplicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}' ({}) by {:?}", self.title, self.id, self.reasons)
+    }
+}
+
+fn push_reason(duplicates: &mut Vec<Duplicate>, metadata: &Metadata, reason: DuplicateReason) {
+    match duplicates.iter_mut().find(|d| d.id == metadata.id) {
+        Some(duplicate) => duplicate.reasons.push(reason),
+        None => duplicates.push(Duplicate {
+            id: metadata.id.clone(),
+            title: metadata.title.clone(),
+            reasons: vec![reason],
+        }),
+    }
+}
+
+/// Find entries other than the candidate itself sharing its platform id, archive path or archive content.
+/// The archive is only hashed when neither the platform id nor the path matched, the hash
+/// calculated then is recorded in the candidate
+pub(crate) fn find_duplicates(
+    read: &ReadTransaction,
+    candidate: &mut Metadata,
+) -> Result<Vec<Duplicate>, LibraryError> {
+    let table = read.open_table(LIB_TABLE)?;
+    let candidate_id = candidate.id.clone();
+    let get = |id: &str| -> Result<Option<Metadata>, LibraryError> {
+        match table.get(id)? {
+            Some(raw) if id != candidate_id => Ok(Some(
+                bson::from_slice::<Metadata>(&raw.value()).map_err(LibraryError::ParseError)?,
+            )),
+            _ => Ok(None),
+        }
+    };
+
+    let mut duplicates = Vec::new();
+
+    if let Some(platform_id) = candidate.platform_id.as_deref().filter(|s| !s.is_empty()) {
+        for id in ids_by_platform(read, &candidate.platform, Some(platform_id))? {
+            if let Some(existed) = get(&id)? {
+                push_reason(&mut duplicates, &existed, DuplicateReason::PlatformId);
+            }
+        }
+    }
+
+    let Some(archive_path) = candidate.archive_path.clone() else {
+        return Ok(duplicates);
+    };
+    let archive_path = archive_path.as_str();
+    for id in ids_by_archive_path(read, archive_path)? {
+        if let Some(existed) = get(&id)? {
+            push_reason(&mut duplicates, &existed, DuplicateReason::ArchivePath);
+        }
+    }
+
+    if !duplicates.is_empty() {
+        return Ok(duplicates);
+    }
+
+    let path = Path::new(archive_path);
+    let candidate_hash = match candidate.archive_hash.clone() {
+        Some(hash) => hash,
+        None if path.exists() => match hash_path(path) {
+            Ok(hash) => {
+                candidate.archive_hash = Some(hash.clone());
+                hash
+            }
+            Err(err) => {
+                warn!("Failed to hash archive {}: {}", archive_path, err);
+                return Ok(duplicates);
+            }
+        },
+        None => return Ok(duplicates),
+    };
+    for id in ids_by_hash(read, &candidate_hash)? {
+        if let Some(existed) = get(&id)?
+            && existed.archive_path.as_deref() != Some(archive_path)
+        {
+            push_reason(&mut duplicates, &existed, DuplicateReason::ContentHash);
+        }
+    }
+
+    // Entries added before hashing was introduced are not in the index,
+    // only files of the same size can have the same content, so hashing them stays rare
+    let Some(size) = path
+        .is_file()
+        .then(|| path.metadata().ok().map(|m| m.len()))
+        .flatten()
+    else {
+        return Ok(duplicates);
+    };
+    for entry in table.iter()? {
+        let (_, raw) = entry?;
+        let existed =
+            bson::from_slice::<Metadata>(&raw.value()).map_err(LibraryError::ParseError)?;
+        let Some(existed_path) = existed.archive_path.as_deref() else {
+            continue;
+        };
+        if existed.id == candidate_id
+            || existed.archive_hash.is_some()
+            || existed.size_bytes != Some(size)
+            || existed_path == archive_path
+            || !Path::new(existed_path).is_file()
+        {
+            continue;
+        }
+
+        match hash_path(existed_path) {
+            Ok(hash) if hash == candidate_hash => {
+                info!(
+                    "Archive {} has the same content as {}",
+                    archive_path, existed_path
+                );
+                push_reason(&mut duplicates, &existed, DuplicateReason::ContentHash);
+            }
+            Ok(_) => {}
+            Err(err) => warn!("Failed to hash archive {}: {}", existed_path, err),
+        }
+    }
+
+    Ok(duplicates)
+}
+
+impl Metadata {
+    /// Fill the fields missing in this entry from another one and unite the tags,
+    /// identity, dates and images of this entry are kept as images are stored per entry
+    pub fn merge_from(&mut self, other: Metadata) {
+        fn fill<T>(target: &mut Option<T>, source: Option<T>) {
+            if target.is_none() {
+                *target = source;
+            }
+        }
+
+        if self.title.is_empty() {
+            self.title = other.title;
+        }
+        fill(&mut self.original_title, other.original_title);
+        if self.content_type == Default::default() {
+            self.content_type = other.content_type;
+        }
+        if self.platform == Default::default() {
+            self.platform = other.platform;
+        }
+        fill(&mut self.platform_id, other.platform_id);
+        fill(&mut self.description, other.description);
+        fill(&mut self.developer, other.developer);
+        fill(&mut self.publisher, other.publisher);
+        fill(&mut self.release_date, other.release_date);
+        if self.archive_path.is_none() {
+            self.archive_path = other.archive_path;
+            self.archive_password = other.archive_password;
+            self.archive_backend = other.archive_backend;
+            self.comic = other.comic;
+            self.size_bytes = other.size_bytes;
+            self.archive_hash = other.archive_hash;
+        }
+        for tag in other.tags {
+            if !self.tags.contains(&tag) {
+                self.tags.push(tag);
+            }
+        }
+        self.mark_updated();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::metadata::{Metadata, Platform, Tag};
+
+    #[test]
+    fn test_merge() {
+        let mut existed = Metadata::new(
+            "Existed".to_string(),
+            Platform::DLSite,
+            Some("RJ01000000".to_string()),
+            "a.7z".to_string(),
+        );
+        existed.tags.push(Tag {
+            name: "A".to_string(),
+            category: None,
+        });
+        let mut incoming = Metadata::new(
+            "Incoming".to_string(),
+            Platform::DLSite,
+            Some("RJ01000000".to_string()),
+            "b.7z".to_string(),
+        );
+        incoming.developer = Some("Circle".to_string());
+        incoming.tags.push(Tag {
+            name: "B".to_string(),
+            category: None,
+        });
+
+        let id = existed.id.clone();
+        existed.merge_from(incoming);
+        assert_eq!(existed.id, id);
+        assert_eq!(existed.title, "Existed");
+        assert_eq!(existed.archive_path.as_deref(), Some("a.7z"));
+        assert_eq!(existed.developer.as_deref(), Some("Circle"));
+        assert_eq!(existed.tags.len(), 2);
+    }
+}
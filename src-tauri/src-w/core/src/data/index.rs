@@ -0,0 +1,338 @@
+use crate::data::library::{LIB_TABLE, LibraryError};
+use crate::data::metadata::{ContentType, Metadata, Platform};
+use crate::data::query::fold_case;
+use redb::{
+    MultimapTableDefinition, ReadTransaction, ReadableTable, TableDefinition, WriteTransaction,
+};
+use std::collections::HashSet;
+use tracing::info;
+
+/// `(platform, platform_id) -> id`, entries without a platform id are stored with an empty one
+const PLATFORM_INDEX: MultimapTableDefinition<(&str, &str), &str> =
+    MultimapTableDefinition::new("INDEX_PLATFORM");
+/// `lowercase tag name -> id`
+const TAG_INDEX: MultimapTableDefinition<&str, &str> = MultimapTableDefinition::new("INDEX_TAG");
+/// `content type -> id`
+const CONTENT_TYPE_INDEX: MultimapTableDefinition<&str, &str> =
+    MultimapTableDefinition::new("INDEX_CONTENT_TYPE");
+/// `archive path -> id`, entries without an archive are not indexed
+const ARCHIVE_INDEX: MultimapTableDefinition<&str, &str> =
+    MultimapTableDefinition::new("INDEX_ARCHIVE");
+/// `archive hash -> id`, entries without a hash are not indexed
+const HASH_INDEX: MultimapTableDefinition<&str, &str> = MultimapTableDefinition::new("INDEX_HASH");
+
+const META_TABLE: TableDefinition<&str, u64> = TableDefinition::new("META");
+const META_INDEX_VERSION: &str = "index_version";
+
+/// Bump this when the layout of any index changes, so existing databases get rebuilt
+const INDEX_VERSION: u64 = 3;
+
+/// Key used in [PLATFORM_INDEX], keeps [Platform::Other] apart from the builtin platforms
+fn platform_key(platform: &Platform) -> String {
+    match platform {
+        Platform::Other(name) => format!("Other/{name}"),
+        _ => platform.to_string(),
+    }
+}
+
+fn tag_key(name: &str) -> String {
+    fold_case(name)
+}
+
+fn content_type_key(content_type: &ContentType) -> String {
+    content_type.to_string()
+}
+
+/// Add the index entries of a [Metadata], must be called in the same transaction as the insert
+pub(crate) fn index_insert(
+    write: &WriteTransaction,
+    metadata: &Metadata,
+) -> Result<(), LibraryError> {
+    let id = metadata.id.as_str();
+    let platform = platform_key(&metadata.platform);
+    let platform_id = metadata.platform_id.as_deref().unwrap_or_default();
+
+    write
+        .open_multimap_table(PLATFORM_INDEX)?
+        .insert((platform.as_str(), platform_id), id)?;
+
+    let mut tags = write.open_multimap_table(TAG_INDEX)?;
+    for tag in &metadata.tags {
+        tags.insert(tag_key(&tag.name).as_str(), id)?;
+    }
+
+    write
+        .open_multimap_table(CONTENT_TYPE_INDEX)?
+        .insert(content_type_key(&metadata.content_type).as_str(), id)?;
+
+    if let Some(archive_path) = metadata.archive_path.as_deref() {
+        write
+            .open_multimap_table(ARCHIVE_INDEX)?
+            .insert(archive_path, id)?;
+    }
+
+    if let Some(archive_hash) = metadata.archive_hash.as_deref() {
+        write
+            .open_multimap_table(HASH_INDEX)?
+            .insert(archive_hash, id)?;
+    }
+
+    Ok(())
+}
+
+/// Remove the index entries of a [Metadata], must be called in the same transaction as the removal
+pub(crate) fn index_remove(
+    write: &WriteTransaction,
+    metadata: &Metadata,
+) -> Result<(), LibraryError> {
+    let id = metadata.id.as_str();
+    let platform = platform_key(&metadata.platform);
+    let platform_id = metadata.platform_id.as_deref().unwrap_or_default();
+
+    write
+        .open_multimap_table(PLATFORM_INDEX)?
+        .remove((platform.as_str(), platform_id), id)?;
+
+    let mut tags = write.open_multimap_table(TAG_INDEX)?;
+    for tag in &metadata.tags {
+        tags.remove(tag_key(&tag.name).as_str(), id)?;
+    }
+
+    write
+        .open_multimap_table(CONTENT_TYPE_INDEX)?
+        .remove(content_type_key(&metadata.content_type).as_str(), id)?;
+
+    if let Some(archive_path) = metadata.archive_path.as_deref() {
+        write
+            .open_multimap_table(ARCHIVE_INDEX)?
+            .remove(archive_path, id)?;
+    }
+
+    if let Some(archive_hash) = metadata.archive_hash.as_deref() {
+        write
+            .open_multimap_table(HASH_INDEX)?
+            .remove(archive_hash, id)?;
+    }
+
+    Ok(())
+}
+
+/// Drop all indexes and rebuild them from the library table, returns the amount of indexed entries
+pub(crate) fn index_rebuild(write: &WriteTransaction) -> Result<usize, LibraryError> {
+    write.delete_multimap_table(PLATFORM_INDEX)?;
+    write.delete_multimap_table(TAG_INDEX)?;
+    write.delete_multimap_table(CONTENT_TYPE_INDEX)?;
+    write.delete_multimap_table(ARCHIVE_INDEX)?;
+    write.delete_multimap_table(HASH_INDEX)?;
+    // Recreate them right away, reading a missing table fails even if the library is empty
+    write.open_multimap_table(PLATFORM_INDEX)?;
+    write.open_multimap_table(TAG_INDEX)?;
+    write.open_multimap_table(CONTENT_TYPE_INDEX)?;
+    write.open_multimap_table(ARCHIVE_INDEX)?;
+    write.open_multimap_table(HASH_INDEX)?;
+
+    let mut count = 0;
+    {
+        let table = write.open_table(LIB_TABLE)?;
+        for entry in table.iter()? {
+            let (_, raw) = entry?;
+            let metadata =
+                bson::from_slice::<Metadata>(&raw.value()).map_err(LibraryError::ParseError)?;
+            index_insert(write, &metadata)?;
+            count += 1;
+        }
+    }
+
+    write
+        .open_table(META_TABLE)?
+        .insert(META_INDEX_VERSION, INDEX_VERSION)?;
+    info!("Rebuilt library indexes for {count} entries");
+
+    Ok(count)
+}
+
+/// Rebuild the indexes if they were created by an older version, or never created at all
+pub(crate) fn index_ensure(write: &WriteTransaction) -> Result<(), LibraryError> {
+    let version = write
+        .open_table(META_TABLE)?
+        .get(META_INDEX_VERSION)?
+        .map(|v| v.value());
+    if version != Some(INDEX_VERSION) {
+        info!(
+            "Library index version {:?} is outdated, expected {INDEX_VERSION}",
+            version
+        );
+        index_rebuild(write)?;
+    }
+    Ok(())
+}
+
+/// Ids of entries with the given platform, and the given platform id if provided
+pub(crate) fn ids_by_platform(
+    read: &ReadTransaction,
+    platform: &Platform,
+    platform_id: Option<&str>,
+) -> Result<HashSet<String>, LibraryError> {
+    let table = read.open_multimap_table(PLATFORM_INDEX)?;
+    let platform = platform_key(platform);
+    let mut ids = HashSet::new();
+    match platform_id {
+        Some(platform_id) => {
+            for id in table.get((platform.as_str(), platform_id))? {
+                ids.insert(id?.value().to_string());
+            }
+        }
+        None => {
+            for entry in table.range((platform.as_str(), "")..)? {
+                let (key, values) = entry?;
+                if key.value().0 != platform {
+                    break;
+                }
+                for id in values {
+                    ids.insert(id?.value().to_string());
+                }
+            }
+        }
+    }
+    Ok(ids)
+}
+
+/// Ids of entries having a tag with the given name, case-insensitive
+pub(crate) fn ids_by_tag(
+    read: &ReadTransaction,
+    name: &str,
+) -> Result<HashSet<String>, LibraryError> {
+    let table = read.open_multimap_table(TAG_INDEX)?;
+    let mut ids = HashSet::new();
+    for id in table.get(tag_key(name).as_str())? {
+        ids.insert(id?.value().to_string());
+    }
+    Ok(ids)
+}
+
+/// Ids of entries with the given [ContentType]
+pub(crate) fn ids_by_content_type(
+    read: &ReadTransaction,
+    content_type: &ContentType,
+) -> Result<HashSet<String>, LibraryError> {
+    let table = read.open_multimap_table(CONTENT_TYPE_INDEX)?;
+    let mut ids = HashSet::new();
+    for id in table.get(content_type_key(content_type).as_str())? {
+        ids.insert(id?.value().to_string());
+    }
+    Ok(ids)
+}
+
+/// Ids of entries using exactly the given archive path
+pub(crate) fn ids_by_archive_path(
+    read: &ReadTransaction,
+    archive_path: &str,
+) -> Result<HashSet<String>, LibraryError> {
+    let table = read.open_multimap_table(ARCHIVE_INDEX)?;
+    let mut ids = HashSet::new();
+    for id in table.get(archive_path)? {
+        ids.insert(id?.value().to_string());
+    }
+    Ok(ids)
+}
+
+/// Ids of entries whose archive has the given hash
+pub(crate) fn ids_by_hash(
+    read: &ReadTransaction,
+    archive_hash: &str,
+) -> Result<HashSet<String>, LibraryError> {
+    let table = read.open_multimap_table(HASH_INDEX)?;
+    let mut ids = HashSet::new();
+    for id in table.get(archive_hash)? {
+        ids.insert(id?.value().to_string());
+    }
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::metadata::Tag;
+    use redb::Database;
+    use redb::backends::InMemoryBackend;
+
+    fn insert(db: &Database, metadata: &Metadata) {
+        let write = db.begin_write().unwrap();
+        {
+            let mut table = write.open_table(LIB_TABLE).unwrap();
+            let old = table
+                .insert(metadata.id.as_str(), bson::to_vec(metadata).unwrap())
+                .unwrap()
+                .map(|raw| bson::from_slice::<Metadata>(&raw.value()).unwrap());
+            if let Some(old) = old {
+                index_remove(&write, &old).unwrap();
+            }
+        }
+        index_insert(&write, metadata).unwrap();
+        write.commit().unwrap();
+    }
+
+    #[test]
+    fn test_index() {
+        let db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+
+        let mut steam = Metadata::new(
+            "Steam Game".to_string(),
+            Platform::Steam,
+            Some("730".to_string()),
+            "a.7z".to_string(),
+        );
+        steam.tags.push(Tag {
+            name: "FPS".to_string(),
+            category: None,
+        });
+        let other = Metadata::new(
+            "Other".to_string(),
+            Platform::Other("Steam".to_string()),
+            Some("730".to_string()),
+            "b.7z".to_string(),
+        );
+        insert(&db, &steam);
+        insert(&db, &other);
+
+        let read = db.begin_read().unwrap();
+        let ids = ids_by_platform(&read, &Platform::Steam, Some("730")).unwrap();
+        assert_eq!(ids, HashSet::from([steam.id.clone()]));
+        let ids = ids_by_platform(&read, &Platform::Other("Steam".to_string()), None).unwrap();
+        assert_eq!(ids, HashSet::from([other.id.clone()]));
+        let ids = ids_by_tag(&read, "fps").unwrap();
+        assert_eq!(ids, HashSet::from([steam.id.clone()]));
+        let ids = ids_by_archive_path(&read, "b.7z").unwrap();
+        assert_eq!(ids, HashSet::from([other.id.clone()]));
+        drop(read);
+
+        // Updating an entry must drop its stale index entries
+        steam.tags.clear();
+        steam.content_type = ContentType::Game;
+        insert(&db, &steam);
+        let read = db.begin_read().unwrap();
+        assert!(ids_by_tag(&read, "fps").unwrap().is_empty());
+        assert!(
+            ids_by_content_type(&read, &ContentType::Unknown)
+                .unwrap()
+                .contains(&other.id)
+        );
+        assert_eq!(
+            ids_by_content_type(&read, &ContentType::Game).unwrap(),
+            HashSet::from([steam.id.clone()])
+        );
+        drop(read);
+
+        // Rebuilding from scratch gives the same result
+        let write = db.begin_write().unwrap();
+        assert_eq!(index_rebuild(&write).unwrap(), 2);
+        write.commit().unwrap();
+        let read = db.begin_read().unwrap();
+        assert_eq!(
+            ids_by_platform(&read, &Platform::Steam, None).unwrap(),
+            HashSet::from([steam.id.clone()])
+        );
+    }
+}
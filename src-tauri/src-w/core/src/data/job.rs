@@ -0,0 +1,641 @@
+use crate::data::deploy::{DeployMethod, DeployOffMode, DeployOffReport};
+use crate::data::duplicate::DuplicatePolicy;
+use crate::data::library::{
+    DriftReport, LibraryError, VerifyReport, lib_delegate_create, lib_delegate_deploy,
+    lib_delegate_deploy_off, lib_delegate_repair, lib_verify, lib_verify_all,
+};
+use crate::data::metadata::Platform;
+use crate::foundation::config::get_clone as config_get_clone;
+use crate::util::flate::{ArchiveOptions, CancelToken, FlateMonitor, FlateProgress};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError, RwLock};
+use std::thread;
+use thiserror::Error;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// An operation run in the background by the job queue
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub enum JobKind {
+    Deploy {
+        id: String,
+        path: String,
+        #[serde(default)]
+        method: DeployMethod,
+    },
+    DeployOff {
+        id: String,
+        /// A single deployment, or all of them if [None]
+        #[serde(default)]
+        deployment: Option<String>,
+        #[serde(default)]
+        mode: DeployOffMode,
+    },
+    Create {
+        title: String,
+        platform: Platform,
+        platform_id: Option<String>,
+        from_path: String,
+        /// The password is never sent back when listing jobs
+        #[serde(default)]
+        options: ArchiveOptions,
+        policy: DuplicatePolicy,
+    },
+    /// Verify a single entry, or the whole library if no id is given
+    Verify { id: Option<String> },
+    /// Restore the missing or modified files of a deployment
+    Repair { id: String, deployment: String },
+}
+
+impl JobKind {
+    /// Jobs sharing a key never run at the same time, nor while the key is held by [job_hold],
+    /// so an entry or a source path is handled by one worker
+    fn key(&self) -> Option<&str> {
+        match self {
+            JobKind::Deploy { id, .. }
+            | JobKind::DeployOff { id, .. }
+            | JobKind::Verify { id: Some(id) }
+            | JobKind::Repair { id, .. } => Some(id),
+            JobKind::Create { from_path, .. } => Some(from_path),
+            JobKind::Verify { id: None } => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed(String),
+    Cancelled,
+}
+
+/// What a succeeded job has produced
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub enum JobOutput {
+    /// Id of the entry deployed or created
+    Entry(String),
+    DeployOff(Vec<DeployOffReport>),
+    Reports(Vec<VerifyReport>),
+    Repair(DriftReport),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct Job {
+    pub id: String,
+    pub kind: JobKind,
+    pub state: JobState,
+    /// Latest progress reported while running
+    pub progress: Option<FlateProgress>,
+    pub output: Option<JobOutput>,
+    pub date_created: DateTime<Utc>,
+    pub date_started: Option<DateTime<Utc>>,
+    pub date_finished: Option<DateTime<Utc>>,
+}
+
+impl Job {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.state,
+            JobState::Succeeded | JobState::Failed(_) | JobState::Cancelled
+        )
+    }
+}
+
+/// Sent to the listeners registered by [job_listen]
+#[derive(Debug, Clone)]
+pub enum JobEvent<'a> {
+    /// The job has been queued, started or finished
+    State(&'a Job),
+    Progress {
+        job: &'a str,
+        progress: &'a FlateProgress,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum JobError {
+    #[error(transparent)]
+    Library(Box<LibraryError>),
+
+    #[error("Job cancelled")]
+    Cancelled,
+
+    #[error("{0} is in use by a running job or task")]
+    Busy(String),
+}
+
+impl From<LibraryError> for JobError {
+    fn from(err: LibraryError) -> Self {
+        JobError::Library(Box::new(err))
+    }
+}
+
+impl JobError {
+    pub fn is_cancelled(&self) -> bool {
+        match self {
+            JobError::Library(err) => err.is_cancelled(),
+            JobError::Cancelled => true,
+            JobError::Busy(_) => false,
+        }
+    }
+}
+
+type JobRunner = fn(&JobKind, &FlateMonitor) -> Result<JobOutput, JobError>;
+type JobListener = Box<dyn Fn(&JobEvent) + Send + Sync>;
+
+struct QueueState {
+    /// All jobs not cleared yet, in submission order
+    jobs: Vec<(Job, CancelToken)>,
+    /// Keys taken by work outside the queue, see [job_hold]
+    held: HashSet<String>,
+    concurrency: usize,
+    workers: usize,
+}
+
+impl QueueState {
+    fn is_busy(&self, key: &str) -> bool {
+        self.held.contains(key)
+            || self
+                .jobs
+                .iter()
+                .any(|(job, _)| job.state == JobState::Running && job.kind.key() == Some(key))
+    }
+
+    /// First queued job whose key is neither held nor taken by a running one
+    fn next_runnable(&self) -> Option<usize> {
+        self.jobs.iter().position(|(job, _)| {
+            job.state == JobState::Queued && job.kind.key().is_none_or(|key| !self.is_busy(key))
+        })
+    }
+
+    fn find(&mut self, id: &str) -> Option<&mut (Job, CancelToken)> {
+        self.jobs.iter_mut().find(|(job, _)| job.id == id)
+    }
+}
+
+struct Shared {
+    runner: JobRunner,
+    state: Mutex<QueueState>,
+    wake: Condvar,
+    listeners: RwLock<Vec<JobListener>>,
+}
+
+impl Shared {
+    /// Nothing panics while holding the lock, so a poisoned state is still consistent
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn emit(&self, event: &JobEvent) {
+        let listeners = self
+            .listeners
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        for listener in listeners.iter() {
+            listener(event);
+        }
+    }
+
+    /// Take the next runnable job and mark it running, [None] once the worker should exit
+    fn take(&self) -> Option<(Job, CancelToken)> {
+        let mut state = self.lock();
+        loop {
+            if state.workers > state.concurrency {
+                state.workers -= 1;
+                return None;
+            }
+            if let Some(index) = state.next_runnable() {
+                let (job, token) = &mut state.jobs[index];
+                job.state = JobState::Running;
+                job.date_started = Some(Utc::now());
+                return Some((job.clone(), token.clone()));
+            }
+            state = self
+                .wake
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    fn run(&self, job: &Job, token: CancelToken) -> (JobState, Option<JobOutput>) {
+        let monitor = FlateMonitor::new(token.clone(), |progress| {
+            if let Some((job, _)) = self.lock().find(&job.id) {
+                job.progress = Some(progress.clone());
+            }
+            self.emit(&JobEvent::Progress {
+                job: &job.id,
+                progress,
+            });
+        });
+        match catch_unwind(AssertUnwindSafe(|| (self.runner)(&job.kind, &monitor))) {
+            Ok(Ok(output)) => (JobState::Succeeded, Some(output)),
+            Ok(Err(err)) if token.is_cancelled() || err.is_cancelled() => {
+                (JobState::Cancelled, None)
+            }
+            Ok(Err(err)) => {
+                error!("Job {} failed: {}", &job.id, err);
+                (JobState::Failed(err.to_string()), None)
+            }
+            Err(_) => {
+                error!("Job {} panicked", &job.id);
+                (JobState::Failed("Job panicked".to_string()), None)
+            }
+        }
+    }
+
+    fn complete(&self, id: &str, state: JobState, output: Option<JobOutput>) {
+        let finished = {
+            let mut queue = self.lock();
+            queue.find(id).map(|(job, _)| {
+                job.state = state;
+                job.output = output;
+                job.date_finished = Some(Utc::now());
+                job.clone()
+            })
+        };
+        // The key is released, a job waiting on it may run now
+        self.wake.notify_all();
+        if let Some(job) = finished {
+            info!("Job {} finished as {:?}", &job.id, &job.state);
+            self.emit(&JobEvent::State(&job));
+        }
+    }
+
+    fn work(self: Arc<Self>) {
+        while let Some((job, token)) = self.take() {
+            self.emit(&JobEvent::State(&job));
+            let (state, output) = self.run(&job, token);
+            self.complete(&job.id, state, output);
+        }
+    }
+}
+
+/// Runs submitted jobs on at most `concurrency` worker threads, spawned on demand
+struct JobQueue {
+    shared: Arc<Shared>,
+}
+
+impl JobQueue {
+    fn new(concurrency: usize, runner: JobRunner) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                runner,
+                state: Mutex::new(QueueState {
+                    jobs: Vec::new(),
+                    held: HashSet::new(),
+                    concurrency: concurrency.max(1),
+                    workers: 0,
+                }),
+                wake: Condvar::new(),
+                listeners: RwLock::new(Vec::new()),
+            }),
+        }
+    }
+
+    fn spawn_workers(&self, state: &mut QueueState) {
+        while state.workers < state.concurrency {
+            state.workers += 1;
+            let shared = self.shared.clone();
+            thread::spawn(move || shared.work());
+        }
+    }
+
+    fn submit(&self, kind: JobKind) -> String {
+        let job = Job {
+            id: Uuid::new_v4().to_string(),
+            kind,
+            state: JobState::Queued,
+            progress: None,
+            output: None,
+            date_created: Utc::now(),
+            date_started: None,
+            date_finished: None,
+        };
+        {
+            let mut state = self.shared.lock();
+            state.jobs.push((job.clone(), CancelToken::new()));
+            self.spawn_workers(&mut state);
+        }
+        self.shared.wake.notify_all();
+        info!("Job {} queued: {:?}", &job.id, &job.kind);
+        self.shared.emit(&JobEvent::State(&job));
+        job.id
+    }
+
+    fn get(&self, id: &str) -> Option<Job> {
+        self.shared.lock().find(id).map(|(job, _)| job.clone())
+    }
+
+    fn list(&self) -> Vec<Job> {
+        let state = self.shared.lock();
+        state.jobs.iter().map(|(job, _)| job.clone()).collect()
+    }
+
+    /// A queued job is cancelled at once, a running one once its operation notices
+    fn cancel(&self, id: &str) -> bool {
+        let cancelled = {
+            let mut state = self.shared.lock();
+            let Some((job, token)) = state.find(id) else {
+                return false;
+            };
+            match job.state {
+                JobState::Queued => {
+                    job.state = JobState::Cancelled;
+                    job.date_finished = Some(Utc::now());
+                    job.clone()
+                }
+                JobState::Running => {
+                    token.cancel();
+                    return true;
+                }
+                _ => return false,
+            }
+        };
+        self.shared.emit(&JobEvent::State(&cancelled));
+        true
+    }
+
+    fn hold(&self, key: &str) -> Result<JobHold, JobError> {
+        let mut state = self.shared.lock();
+        if state.is_busy(key) {
+            return Err(JobError::Busy(key.to_string()));
+        }
+        state.held.insert(key.to_string());
+        Ok(JobHold {
+            key: key.to_string(),
+            shared: self.shared.clone(),
+        })
+    }
+
+    /// Removes finished jobs, returns the amount removed
+    fn clear(&self) -> usize {
+        let mut state = self.shared.lock();
+        let before = state.jobs.len();
+        state.jobs.retain(|(job, _)| !job.is_finished());
+        before - state.jobs.len()
+    }
+
+    fn set_concurrency(&self, concurrency: usize) {
+        {
+            let mut state = self.shared.lock();
+            state.concurrency = concurrency.max(1);
+            if state.workers > 0 {
+                self.spawn_workers(&mut state);
+            }
+        }
+        // Wake idle workers so the extra ones can exit
+        self.shared.wake.notify_all();
+    }
+
+    fn listen(&self, listener: JobListener) {
+        self.shared
+            .listeners
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(listener);
+    }
+}
+
+/// Keeps jobs off a key until dropped, see [job_hold]
+pub struct JobHold {
+    key: String,
+    shared: Arc<Shared>,
+}
+
+impl Drop for JobHold {
+    fn drop(&mut self) {
+        self.shared.lock().held.remove(&self.key);
+        // A job waiting on the key may run now
+        self.shared.wake.notify_all();
+    }
+}
+
+fn queue() -> &'static JobQueue {
+    static QUEUE: OnceLock<JobQueue> = OnceLock::new();
+    QUEUE.get_or_init(|| {
+        let concurrency = match config_get_clone() {
+            Ok(config) => config.job_concurrency(),
+            Err(err) => {
+                warn!("Failed to read job concurrency from config: {err}");
+                1
+            }
+        };
+        info!("Job queue running with concurrency {}", concurrency);
+        JobQueue::new(concurrency, job_run)
+    })
+}
+
+fn job_run(kind: &JobKind, monitor: &FlateMonitor) -> Result<JobOutput, JobError> {
+    match kind {
+        JobKind::Deploy { id, path, method } => {
+            lib_delegate_deploy(id, path, *method, monitor)?;
+            Ok(JobOutput::Entry(id.clone()))
+        }
+        JobKind::DeployOff {
+            id,
+            deployment,
+            mode,
+        } => Ok(JobOutput::DeployOff(lib_delegate_deploy_off(
+            id,
+            deployment.as_deref(),
+            *mode,
+        )?)),
+        JobKind::Create {
+            title,
+            platform,
+            platform_id,
+            from_path,
+            options,
+            policy,
+        } => {
+            let id = lib_delegate_create(
+                title.clone(),
+                platform.clone(),
+                platform_id.clone(),
+                from_path.clone(),
+                options.clone(),
+                *policy,
+                monitor,
+            )?;
+            Ok(JobOutput::Entry(id))
+        }
+        JobKind::Verify { id: Some(id) } => Ok(JobOutput::Reports(vec![lib_verify(id)?])),
+        JobKind::Repair { id, deployment } => Ok(JobOutput::Repair(lib_delegate_repair(
+            id, deployment, monitor,
+        )?)),
+        JobKind::Verify { id: None } => {
+            let reports = lib_verify_all(|progress| {
+                if progress.current == 1 {
+                    monitor.begin(None, Some(progress.total as u64));
+                } else {
+                    monitor.leave();
+                }
+                match monitor.enter(progress.title.clone()) {
+                    Ok(()) => ControlFlow::Continue(()),
+                    Err(_) => ControlFlow::Break(()),
+                }
+            })?;
+            if !reports.is_empty() {
+                monitor.leave();
+            }
+            monitor.finish();
+            Ok(JobOutput::Reports(reports))
+        }
+    }
+}
+
+/// Queue a job, returns its id
+pub fn job_submit(kind: JobKind) -> String {
+    queue().submit(kind)
+}
+
+pub fn job_get(id: &str) -> Option<Job> {
+    queue().get(id)
+}
+
+/// All jobs not cleared yet, in submission order
+pub fn job_list() -> Vec<Job> {
+    queue().list()
+}
+
+/// Cancel a queued or running job, returns false if it does not exist or has finished
+pub fn job_cancel(id: &str) -> bool {
+    queue().cancel(id)
+}
+
+/// Forget finished jobs, returns the amount removed
+pub fn job_clear() -> usize {
+    queue().clear()
+}
+
+/// Take the key of an entry or a source path for work run outside the queue, such as a synchronous
+/// command. Jobs with the key wait until the hold is dropped, fails if a running job or another
+/// hold has the key
+pub fn job_hold(key: &str) -> Result<JobHold, JobError> {
+    queue().hold(key)
+}
+
+/// Change the maximum amount of jobs running at the same time, at least one
+pub fn job_set_concurrency(concurrency: usize) {
+    queue().set_concurrency(concurrency)
+}
+
+/// Register a listener for state changes and progress of all jobs, called on the worker threads
+pub fn job_listen(listener: impl Fn(&JobEvent) + Send + Sync + 'static) {
+    queue().listen(Box::new(listener))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    /// Deploy runs until cancelled, deploy off succeeds at once
+    fn test_runner(kind: &JobKind, monitor: &FlateMonitor) -> Result<JobOutput, JobError> {
+        match kind {
+            JobKind::Deploy { .. } => {
+                while !monitor.is_cancelled() {
+                    thread::sleep(Duration::from_millis(5));
+                }
+                Err(JobError::Cancelled)
+            }
+            JobKind::DeployOff { id, .. } => Ok(JobOutput::Entry(id.clone())),
+            _ => unreachable!(),
+        }
+    }
+
+    fn wait_for(queue: &JobQueue, id: &str, state: JobState) -> Job {
+        let start = Instant::now();
+        loop {
+            let job = queue.get(id).unwrap();
+            if job.state == state {
+                return job;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "Job {id} stuck in {:?}",
+                job.state
+            );
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_queue() {
+        let queue = JobQueue::new(2, test_runner);
+        let deploy = |id: &str| JobKind::Deploy {
+            id: id.to_string(),
+            path: "unused".to_string(),
+            method: DeployMethod::default(),
+        };
+        let deploy_off = |id: &str| JobKind::DeployOff {
+            id: id.to_string(),
+            deployment: None,
+            mode: DeployOffMode::default(),
+        };
+
+        let a = queue.submit(deploy("a"));
+        wait_for(&queue, &a, JobState::Running);
+
+        // Same key as a running job, waits even with a free worker
+        let a_off = queue.submit(deploy_off("a"));
+        let b_off = queue.submit(deploy_off("b"));
+        let b_off = wait_for(&queue, &b_off, JobState::Succeeded);
+        assert_eq!(b_off.output, Some(JobOutput::Entry("b".to_string())));
+        assert_eq!(queue.get(&a_off).unwrap().state, JobState::Queued);
+
+        assert!(queue.cancel(&a));
+        wait_for(&queue, &a, JobState::Cancelled);
+        wait_for(&queue, &a_off, JobState::Succeeded);
+        assert!(!queue.cancel(&a_off));
+
+        // A queued job is cancelled without running
+        queue.set_concurrency(1);
+        let c = queue.submit(deploy("c"));
+        wait_for(&queue, &c, JobState::Running);
+        let d = queue.submit(deploy_off("d"));
+        assert!(queue.cancel(&d));
+        assert_eq!(queue.get(&d).unwrap().state, JobState::Cancelled);
+        assert!(queue.cancel(&c));
+        wait_for(&queue, &c, JobState::Cancelled);
+
+        assert_eq!(queue.clear(), 5);
+        assert!(queue.list().is_empty());
+    }
+
+    #[test]
+    fn test_hold() {
+        let queue = JobQueue::new(2, test_runner);
+        let hold = queue.hold("a").unwrap();
+        assert!(matches!(queue.hold("a"), Err(JobError::Busy(_))));
+
+        // Waits for the hold to be dropped
+        let a_off = queue.submit(JobKind::DeployOff {
+            id: "a".to_string(),
+            deployment: None,
+            mode: DeployOffMode::default(),
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(queue.get(&a_off).unwrap().state, JobState::Queued);
+        drop(hold);
+        wait_for(&queue, &a_off, JobState::Succeeded);
+
+        // A running job keeps the key from holds
+        let b = queue.submit(JobKind::Deploy {
+            id: "b".to_string(),
+            path: "unused".to_string(),
+            method: DeployMethod::default(),
+        });
+        wait_for(&queue, &b, JobState::Running);
+        assert!(matches!(queue.hold("b"), Err(JobError::Busy(_))));
+        assert!(queue.cancel(&b));
+        wait_for(&queue, &b, JobState::Cancelled);
+        assert!(queue.hold("b").is_ok());
+    }
+}
@@ -1,11 +1,28 @@
-use crate::data::metadata::{Metadata, MetadataError, Platform};
-use crate::foundation::config::{get_clone as config_get_clone, get_data_dir};
+use crate::data::artwork::{Artwork, ArtworkError, artwork_gc, artwork_read, artwork_store};
+use crate::data::comic::ComicPage;
+use crate::data::deploy::{DeployMethod, DeployOffMode, DeployOffReport, Deployment, DriftStatus};
+use crate::data::duplicate::{Duplicate, DuplicatePolicy, find_duplicates};
+use crate::data::index::{
+    ids_by_content_type, ids_by_platform, ids_by_tag, index_ensure, index_insert, index_rebuild,
+    index_remove,
+};
+use crate::data::metadata::{Metadata, MetadataError, Platform, VerifyStatus};
+use crate::data::query::{LibraryFilter, LibraryPage, LibraryQuery};
+use crate::foundation::config::{
+    get_clone as config_get_clone, get_data_dir, set_password_candidates,
+};
+use crate::foundation::secret::{
+    Secret, SecretError, SecretRotation, secret_pending, secret_rotate,
+};
+use crate::util::flate::{ArchiveEntry, ArchiveOptions, FlateMonitor};
 use chrono::Utc;
-use redb::{Database, ReadableTable, TableDefinition};
+use redb::{Database, ReadTransaction, ReadableTable, TableDefinition, WriteTransaction};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::ops::ControlFlow;
 use std::path::Path;
-use std::sync::OnceLock;
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError, RwLock, RwLockReadGuard};
 use std::time::SystemTime;
 use thiserror::Error;
 use tracing::{error, info, warn};
@@ -14,7 +31,7 @@ const LIB_FILE_NAME: &str = "library.redb";
 const LIB_FILE_EXPORT: &str = "library.json";
 const LIB_FILE_STEM: &str = "library";
 const LIB_FILE_EXT: &str = "redb";
-const LIB_TABLE: TableDefinition<&str, Vec<u8>> = TableDefinition::new("LIBRARY");
+pub(crate) const LIB_TABLE: TableDefinition<&str, Vec<u8>> = TableDefinition::new("LIBRARY");
 
 const ARCHIVE_DIR_NAME: &str = "archive";
 const BACKUP_DIR_NAME: &str = "backup";
@@ -38,7 +55,7 @@ fn library() -> &'static Database {
                     entry
                         .metadata()
                         .and_then(|m| m.modified())
-                        .unwrap_or_else(|_| SystemTime::UNIX_EPOCH)
+                        .unwrap_or(SystemTime::UNIX_EPOCH)
                 });
                 if let Some(oldest) = backups.first() {
                     if let Err(e) = fs::remove_file(oldest.path()) {
@@ -68,27 +85,157 @@ fn library() -> &'static Database {
     })
 }
 
-fn lib_internal_add_nocheck(metadata: Metadata) -> Result<(), LibraryError> {
+/// Shared by everything sealing secrets until they are stored, taken alone by [lib_set_passphrase]
+/// so nothing is sealed with a key that is being replaced
+fn secrets_lock() -> &'static RwLock<()> {
+    static LOCK: RwLock<()> = RwLock::new(());
+    &LOCK
+}
+
+fn secrets_read() -> RwLockReadGuard<'static, ()> {
+    secrets_lock()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+fn lib_internal_add_nocheck(mut metadata: Metadata) -> Result<(), LibraryError> {
+    let _secrets = secrets_read();
+    metadata.seal_secrets()?;
     let to_save = bson::to_vec(&metadata).map_err(LibraryError::SerializeError)?;
     let write = library().begin_write()?;
     {
         let mut table = write.open_table(LIB_TABLE)?;
-        table.insert(metadata.id.as_str(), to_save)?;
+        if let Some(old) = table.insert(metadata.id.as_str(), to_save)? {
+            let old =
+                bson::from_slice::<Metadata>(&old.value()).map_err(LibraryError::ParseError)?;
+            index_remove(&write, &old)?;
+        }
     }
+    index_insert(&write, &metadata)?;
     write.commit()?;
     Ok(())
 }
 
+/// Change an entry as it is stored now, read and written in one transaction
+/// so changes made meanwhile by others are kept
+fn lib_internal_update(
+    id: &str,
+    update: impl FnOnce(&mut Metadata),
+) -> Result<Metadata, LibraryError> {
+    let _secrets = secrets_read();
+    let write = library().begin_write()?;
+    let metadata = {
+        let mut table = write.open_table(LIB_TABLE)?;
+        let raw = table
+            .get(id)?
+            .map(|raw| raw.value())
+            .ok_or_else(|| LibraryError::NotFound(id.to_string()))?;
+        let old = bson::from_slice::<Metadata>(&raw).map_err(LibraryError::ParseError)?;
+        let mut metadata = old.clone();
+        update(&mut metadata);
+        metadata.seal_secrets()?;
+        let to_save = bson::to_vec(&metadata).map_err(LibraryError::SerializeError)?;
+        table.insert(id, to_save)?;
+        index_remove(&write, &old)?;
+        metadata
+    };
+    index_insert(&write, &metadata)?;
+    write.commit()?;
+    Ok(metadata)
+}
+
 pub fn lib_fresh() -> Result<(), LibraryError> {
+    if let Some(pending) = secret_pending()? {
+        warn!(
+            "A master passphrase change was interrupted, {} holds the new key \
+            if stored passwords can't be revealed anymore",
+            pending.display()
+        );
+    }
+    let _secrets = secrets_read();
     let write = library().begin_write()?;
     {
         let table = write.open_table(LIB_TABLE)?;
         table.get("fresh!")?;
     }
+    index_ensure(&write)?;
+    lib_internal_migrate(&write)?;
     write.commit()?;
     Ok(())
 }
 
+/// Rewrite entries stored by an older version, see [Metadata::migrate],
+/// and seal plain passwords if the secrets are not locked
+fn lib_internal_migrate(write: &WriteTransaction) -> Result<(), LibraryError> {
+    let mut table = write.open_table(LIB_TABLE)?;
+    let mut migrated = Vec::new();
+    for entry in table.iter()? {
+        let (_, raw) = entry?;
+        let mut metadata =
+            bson::from_slice::<Metadata>(&raw.value()).map_err(LibraryError::ParseError)?;
+        let changed = metadata.migrate();
+        if lib_internal_seal(&mut metadata) || changed {
+            migrated.push(metadata);
+        }
+    }
+    for metadata in &migrated {
+        let to_save = bson::to_vec(metadata).map_err(LibraryError::SerializeError)?;
+        table.insert(metadata.id.as_str(), to_save)?;
+    }
+    if !migrated.is_empty() {
+        info!("Migrated {} entries", migrated.len());
+    }
+    Ok(())
+}
+
+/// Drops and rebuilds all secondary indexes, returns the amount of indexed entries
+pub fn lib_index_rebuild() -> Result<usize, LibraryError> {
+    let write = library().begin_write()?;
+    let count = index_rebuild(&write)?;
+    write.commit()?;
+    Ok(count)
+}
+
+fn lib_internal_get_many(
+    read: &ReadTransaction,
+    ids: impl IntoIterator<Item = String>,
+) -> Result<Vec<Metadata>, LibraryError> {
+    let table = read.open_table(LIB_TABLE)?;
+    let mut entries = Vec::new();
+    for id in ids {
+        if let Some(raw) = table.get(id.as_str())? {
+            let metadata =
+                bson::from_slice::<Metadata>(&raw.value()).map_err(LibraryError::ParseError)?;
+            entries.push(metadata);
+        }
+    }
+    Ok(entries)
+}
+
+/// Narrow down the ids to look at using the indexes, [None] if the filter has no indexed field
+fn lib_internal_candidates(
+    read: &ReadTransaction,
+    filter: &LibraryFilter,
+) -> Result<Option<HashSet<String>>, LibraryError> {
+    let mut candidates: Option<HashSet<String>> = None;
+    let mut narrow = |ids: HashSet<String>| {
+        candidates = Some(match candidates.take() {
+            Some(existing) => existing.intersection(&ids).cloned().collect(),
+            None => ids,
+        });
+    };
+    if let Some(platform) = filter.platform.as_ref() {
+        narrow(ids_by_platform(read, platform, None)?);
+    }
+    if let Some(content_type) = filter.content_type.as_ref() {
+        narrow(ids_by_content_type(read, content_type)?);
+    }
+    if let Some(tag_name) = filter.tag_name.as_deref() {
+        narrow(ids_by_tag(read, tag_name)?);
+    }
+    Ok(candidates)
+}
+
 /// Gets a [Metadata] copy with the given id, if it exists
 pub fn lib_get(id: &str) -> Result<Metadata, LibraryError> {
     let read = library().begin_read()?;
@@ -118,17 +265,100 @@ pub fn lib_get_all() -> Result<Library, LibraryError> {
     Ok(Library { entries })
 }
 
-/// Adds a [Metadata] to the library
-pub fn lib_add(mut data: Metadata) -> Result<(), LibraryError> {
+/// Gets the entries matching the given [LibraryQuery], sorted and paginated
+pub fn lib_query(query: &LibraryQuery) -> Result<LibraryPage, LibraryError> {
+    let read = library().begin_read()?;
+    let entries = match lib_internal_candidates(&read, &query.filter)? {
+        Some(ids) => lib_internal_get_many(&read, ids)?
+            .into_iter()
+            .filter(|metadata| query.filter.matches(metadata))
+            .collect(),
+        None => {
+            let table = read.open_table(LIB_TABLE)?;
+            let mut entries = Vec::new();
+            for entry in table.iter()? {
+                let (_, raw) = entry?;
+                let metadata =
+                    bson::from_slice::<Metadata>(&raw.value()).map_err(LibraryError::ParseError)?;
+                if query.filter.matches(&metadata) {
+                    entries.push(metadata);
+                }
+            }
+            entries
+        }
+    };
+    Ok(query.paginate(entries))
+}
+
+/// Gets the entries with the given [Platform] and platform id, using the index
+pub fn lib_find_by_platform_id(
+    platform: &Platform,
+    platform_id: &str,
+) -> Result<Vec<Metadata>, LibraryError> {
+    let read = library().begin_read()?;
+    let ids = ids_by_platform(&read, platform, Some(platform_id))?;
+    lib_internal_get_many(&read, ids)
+}
+
+/// Gets the existing entries that the given [Metadata] would duplicate
+pub fn lib_find_duplicates(candidate: &mut Metadata) -> Result<Vec<Duplicate>, LibraryError> {
+    let read = library().begin_read()?;
+    find_duplicates(&read, candidate)
+}
+
+/// Adds a [Metadata] to the library, rejecting duplicates of existing entries
+pub fn lib_add(data: Metadata) -> Result<(), LibraryError> {
+    lib_add_with(data, DuplicatePolicy::default()).map(|_| ())
+}
+
+/// Adds a [Metadata] to the library, new entries are checked for duplicates under the given [DuplicatePolicy].
+/// Returns the id of the entry holding the data, which differs from the given one after a merge
+pub fn lib_add_with(mut data: Metadata, policy: DuplicatePolicy) -> Result<String, LibraryError> {
     if let Ok(existed) = lib_get(&data.id) {
         // Update mode
         if data.archive_path != existed.archive_path {
             // Update size
             let _ = data.calculate_size();
+            let _ = data.calculate_hash();
+        }
+        if data.archive_path != existed.archive_path || data.content_type != existed.content_type {
+            lib_internal_scan_comic(&mut data);
         }
         data.mark_updated();
+    } else {
+        if data.comic.is_none() {
+            lib_internal_scan_comic(&mut data);
+        }
+        let duplicates = match policy {
+            DuplicatePolicy::Force => Vec::new(),
+            _ => lib_find_duplicates(&mut data)?,
+        };
+        if let Some(first) = duplicates.first() {
+            if policy == DuplicatePolicy::Merge {
+                let mut existed = lib_get(&first.id)?;
+                info!("Merging '{}' into existing {}", &data.title, first);
+                let adopted = existed.archive_path.is_none();
+                existed.merge_from(data);
+                if adopted && existed.archive_hash.is_none() {
+                    let _ = existed.calculate_hash();
+                }
+                let id = existed.id.clone();
+                lib_internal_add_nocheck(existed)?;
+                return Ok(id);
+            }
+            warn!("Rejected '{}' duplicating existing entries", &data.title);
+            return Err(LibraryError::Duplicate(duplicates));
+        }
+        // Only hashed by the duplicate check when nothing cheaper matched
+        if data.archive_hash.is_none() {
+            let _ = data.calculate_hash();
+        }
     }
-    lib_internal_add_nocheck(data)
+    let _artwork = artwork_lock();
+    lib_internal_comic_cover(&mut data);
+    let id = data.id.clone();
+    lib_internal_add_nocheck(data)?;
+    Ok(id)
 }
 
 /// No difference from [lib_add], just migration
@@ -141,14 +371,161 @@ pub fn lib_del(id: &str) -> Result<(), LibraryError> {
     let write = library().begin_write()?;
     {
         let mut table = write.open_table(LIB_TABLE)?;
-        table.remove(id)?;
+        if let Some(old) = table.remove(id)? {
+            let old =
+                bson::from_slice::<Metadata>(&old.value()).map_err(LibraryError::ParseError)?;
+            index_remove(&write, &old)?;
+        }
     }
     write.commit()?;
+    if let Err(err) = lib_gc_artwork() {
+        warn!("Failed to remove orphaned images: {}", err);
+    }
     Ok(())
 }
 
+/// Remove the stored images no entry refers to any more, returns the number of files removed
+pub fn lib_gc_artwork() -> Result<usize, LibraryError> {
+    let _artwork = artwork_lock();
+    let live = lib_get_all()?
+        .entries
+        .into_iter()
+        .map(|metadata| {
+            let hashes = metadata.artworks().map(|a| a.hash.clone()).collect();
+            (metadata.id, hashes)
+        })
+        .collect::<HashMap<_, HashSet<_>>>();
+    Ok(artwork_gc(&live)?)
+}
+
+/// Store an image as the cover of an entry, replacing the former one
+pub fn lib_set_cover(id: &str, data: &[u8]) -> Result<Metadata, LibraryError> {
+    let _artwork = artwork_lock();
+    let mut metadata = lib_get(id)?;
+    metadata.cover = Some(artwork_store(id, data)?);
+    metadata.mark_updated();
+    lib_internal_add_nocheck(metadata.clone())?;
+    Ok(metadata)
+}
+
+/// Store an image as a screenshot of an entry, the same image is only added once
+pub fn lib_add_screenshot(id: &str, data: &[u8]) -> Result<Metadata, LibraryError> {
+    let _artwork = artwork_lock();
+    let mut metadata = lib_get(id)?;
+    let artwork = artwork_store(id, data)?;
+    if !metadata.screenshots.contains(&artwork) {
+        metadata.screenshots.push(artwork);
+        metadata.mark_updated();
+        lib_internal_add_nocheck(metadata.clone())?;
+    }
+    Ok(metadata)
+}
+
+/// Drop the cover or screenshot with the given hash from an entry, the file goes with the next
+/// garbage collection
+pub fn lib_remove_artwork(id: &str, hash: &str) -> Result<Metadata, LibraryError> {
+    let mut metadata = lib_get(id)?;
+    let count = metadata.artworks().count();
+    metadata.cover.take_if(|cover| cover.hash == hash);
+    metadata.screenshots.retain(|artwork| artwork.hash != hash);
+    if metadata.artworks().count() != count {
+        metadata.mark_updated();
+        lib_internal_add_nocheck(metadata.clone())?;
+    }
+    Ok(metadata)
+}
+
+/// Read the cover or a screenshot of an entry by its hash, or the thumbnail of it
+pub fn lib_artwork(
+    id: &str,
+    hash: &str,
+    thumbnail: bool,
+) -> Result<(Artwork, Vec<u8>), LibraryError> {
+    let metadata = lib_get(id)?;
+    let Some(artwork) = metadata.artworks().find(|artwork| artwork.hash == hash) else {
+        return Err(LibraryError::NotFound(format!("{id}/{hash}")));
+    };
+    let data = artwork_read(id, artwork, thumbnail)?;
+    Ok((artwork.clone(), data))
+}
+
+/// Scan the pages of a comic entry, a failure is only logged and leaves the entry without them
+fn lib_internal_scan_comic(metadata: &mut Metadata) {
+    if let Err(err) = metadata.scan_comic() {
+        warn!("Failed to scan pages of '{}': {}", &metadata.title, err);
+    }
+}
+
+/// Held from storing an image until the entry referring to it is written, and while
+/// [lib_gc_artwork] collects, so an image not referred to yet is never taken for an orphan
+fn artwork_lock() -> MutexGuard<'static, ()> {
+    static LOCK: Mutex<()> = Mutex::new(());
+    LOCK.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Use the first page of a comic without a cover as its cover, a failure is only logged
+fn lib_internal_comic_cover(metadata: &mut Metadata) {
+    if metadata.cover.is_some() || metadata.comic.is_none() {
+        return;
+    }
+    let stored = metadata
+        .comic_page(0)
+        .map_err(|e| LibraryError::ComicError(e, metadata.id.clone()))
+        .and_then(|page| Ok(artwork_store(&metadata.id, &page.data)?));
+    match stored {
+        Ok(cover) => metadata.cover = Some(cover),
+        Err(err) => warn!(
+            "Failed to store the cover of '{}': {}",
+            &metadata.title, err
+        ),
+    }
+}
+
+/// Seal the password of an entry, a failure is only logged so the entry is sealed on a later try
+fn lib_internal_seal(metadata: &mut Metadata) -> bool {
+    metadata.seal_secrets().unwrap_or_else(|err| {
+        warn!("Failed to seal password of '{}': {}", &metadata.title, err);
+        false
+    })
+}
+
+/// Seal the password candidates written in plain into the config,
+/// a failure is only logged so they are sealed on a later try
+fn lib_internal_seal_candidates() {
+    let _secrets = secrets_read();
+    let Ok(config) = config_get_clone() else {
+        return;
+    };
+    let mut candidates = config.password_candidates().to_vec();
+    if candidates.iter().all(Secret::is_sealed) {
+        return;
+    }
+    let sealed = candidates
+        .iter_mut()
+        .try_for_each(Secret::seal)
+        .map_err(LibraryError::from)
+        .and_then(|_| Ok(set_password_candidates(candidates)?));
+    if let Err(err) = sealed {
+        warn!("Failed to seal password candidates: {}", err);
+    }
+}
+
+/// Export the library to JSON, passwords are written sealed and plain ones are left out.
+/// Images are referenced by hash, the files stay in the artwork directory next to the export
 pub fn lib_export() -> Result<(), LibraryError> {
-    let all = lib_get_all()?;
+    let _secrets = secrets_read();
+    let mut all = lib_get_all()?;
+    for metadata in all.entries.iter_mut() {
+        lib_internal_seal(metadata);
+        if metadata
+            .archive_password
+            .as_ref()
+            .is_some_and(|p| !p.is_sealed())
+        {
+            warn!("Redacting plain password of '{}'", &metadata.title);
+            metadata.archive_password = None;
+        }
+    }
     let serialized = serde_json::to_string_pretty(&all)?;
     let path = get_data_dir()?.join(LIB_FILE_EXPORT);
     fs::write(&path, serialized)?;
@@ -168,14 +545,22 @@ pub fn lib_import() -> Result<bool, LibraryError> {
         lib.entries.len(),
         path.display()
     );
+    let _secrets = secrets_read();
     let write = library().begin_write()?;
     {
         let mut table = write.open_table(LIB_TABLE)?;
-        for entry in lib.entries {
-            table.insert(
+        for mut entry in lib.entries {
+            entry.migrate();
+            lib_internal_seal(&mut entry);
+            if let Some(old) = table.insert(
                 entry.id.as_str(),
                 &bson::to_vec(&entry).map_err(LibraryError::SerializeError)?,
-            )?;
+            )? {
+                let old =
+                    bson::from_slice::<Metadata>(&old.value()).map_err(LibraryError::ParseError)?;
+                index_remove(&write, &old)?;
+            }
+            index_insert(&write, &entry)?;
         }
     }
     write.commit()?;
@@ -189,8 +574,28 @@ pub fn lib_delegate_create(
     platform: Platform,
     platform_id: Option<String>,
     from_path: String,
-    password: Option<String>,
-) -> Result<(), LibraryError> {
+    options: ArchiveOptions,
+    policy: DuplicatePolicy,
+    monitor: &FlateMonitor,
+) -> Result<String, LibraryError> {
+    // Check the platform id first, no need to compress anything if it would be rejected
+    let mut probe = Metadata::builder()
+        .title(title.clone())
+        .platform(platform.clone())
+        .maybe_platform_id(platform_id.clone())
+        .build();
+    let duplicates = lib_find_duplicates(&mut probe)?;
+    if let Some(first) = duplicates.first() {
+        match policy {
+            DuplicatePolicy::Reject => return Err(LibraryError::Duplicate(duplicates)),
+            DuplicatePolicy::Merge if lib_get(&first.id)?.archive_path.is_some() => {
+                info!("{} already has an archive, skipped creating one", first);
+                return lib_add_with(probe, policy);
+            }
+            _ => {}
+        }
+    }
+
     let config = config_get_clone()?;
     let path_to_dir = config
         .data_dir()
@@ -199,51 +604,380 @@ pub fn lib_delegate_create(
     if !path_to_dir.exists() {
         fs::create_dir_all(&path_to_dir)?;
     }
-    let path_to_archive = path_to_dir.join(format!(
-        "{}.7z",
-        platform_id
-            .clone()
-            .take_if(|s| !s.is_empty())
-            .unwrap_or(Utc::now().format("ANONYMOUS-%Y%m%d-%H%M%S").to_string()),
-    ));
+    let stem = platform_id
+        .clone()
+        .take_if(|s| !s.is_empty())
+        .unwrap_or(Utc::now().format("ANONYMOUS-%Y%m%d-%H%M%S").to_string());
+    let mut path_to_archive = path_to_dir.join(format!("{stem}.{}", options.format.extension()));
+    if path_to_archive.exists() {
+        // Never write into the archive of another entry
+        path_to_archive.set_file_name(format!(
+            "{stem}-{}.{}",
+            Utc::now().format("%Y%m%d-%H%M%S"),
+            options.format.extension(),
+        ));
+    }
 
     info!("Creating new archive at: {}", path_to_archive.display());
 
-    let metadata = Metadata::new_on_create_archive(
+    let metadata = match Metadata::new_on_create_archive(
         title,
         platform,
         platform_id,
         from_path,
-        path_to_archive,
-        password,
-    )
-    .map_err(LibraryError::CreateError)?;
+        &path_to_archive,
+        &options,
+        monitor,
+    ) {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            // Cancelled or failed halfway, the partial archive is of no use
+            if path_to_archive.exists() {
+                info!("Removing partial archive: {}", path_to_archive.display());
+                lib_internal_remove_archive(&path_to_archive);
+            }
+            return Err(LibraryError::CreateError(err));
+        }
+    };
+
+    let result = lib_add_with(metadata, policy);
+    let keep_archive = match result.as_ref() {
+        Ok(id) => match lib_get(id) {
+            Ok(stored) => stored
+                .archive_path
+                .is_some_and(|p| Path::new(&p) == path_to_archive),
+            Err(err) => {
+                // The stored entry may point to it, better left behind than missing
+                warn!(
+                    "Kept archive {} as entry {} can't be read: {}",
+                    path_to_archive.display(),
+                    id,
+                    err
+                );
+                true
+            }
+        },
+        Err(_) => false,
+    };
+    if !keep_archive {
+        info!("Removing unused archive: {}", path_to_archive.display());
+        lib_internal_remove_archive(&path_to_archive);
+    }
+    result
+}
+
+/// Remove an archive created for nothing, a failure is only logged so the outcome of
+/// the creation is what gets returned
+fn lib_internal_remove_archive(path: &Path) {
+    if let Err(err) = fs::remove_file(path) {
+        error!("Failed to remove archive {}: {}", path.display(), err);
+    }
+}
+
+/// Encrypt the archive passwords and the password candidates with a key derived from a new
+/// master passphrase, or with a new key file in the data directory if [None].
+/// The secrets must be unlocked with the current passphrase first
+pub fn lib_set_passphrase(passphrase: Option<&str>) -> Result<usize, LibraryError> {
+    let _secrets = secrets_lock()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner);
+    let rotation = secret_rotate(passphrase)?;
+    let mut candidates = config_get_clone()?
+        .password_candidates()
+        .iter()
+        .map(|candidate| candidate.reveal().map(Secret::new))
+        .collect::<Result<Vec<_>, _>>()?;
+    for candidate in candidates.iter_mut() {
+        rotation.seal(candidate)?;
+    }
+    // Once the entries are committed the new key must be on disk already
+    rotation.stage()?;
+    let count = match lib_internal_reseal(&rotation) {
+        Ok(count) => count,
+        Err(err) => {
+            rotation.discard();
+            return Err(err);
+        }
+    };
+    if let Err(err) = set_password_candidates(candidates) {
+        // The entries are committed already, the candidates have to be entered again
+        error!(
+            "Failed to save password candidates sealed with the new key: {}",
+            err
+        );
+    }
+    rotation.apply()?;
+    info!("Sealed {} passwords with the new key", count);
+    Ok(count)
+}
+
+/// Seal the archive password of every entry with the pending key, returns the amount sealed
+fn lib_internal_reseal(rotation: &SecretRotation) -> Result<usize, LibraryError> {
+    let write = library().begin_write()?;
+    let count = {
+        let mut table = write.open_table(LIB_TABLE)?;
+        let mut entries = Vec::new();
+        for entry in table.iter()? {
+            let (_, raw) = entry?;
+            let metadata =
+                bson::from_slice::<Metadata>(&raw.value()).map_err(LibraryError::ParseError)?;
+            if let Some(password) = metadata.archive_password.as_ref() {
+                let plain = password.reveal()?;
+                entries.push((metadata, plain));
+            }
+        }
 
-    lib_internal_add_nocheck(metadata)
+        let count = entries.len();
+        for (mut metadata, plain) in entries {
+            let mut password = Secret::new(plain);
+            rotation.seal(&mut password)?;
+            metadata.archive_password = Some(password);
+            let to_save = bson::to_vec(&metadata).map_err(LibraryError::SerializeError)?;
+            table.insert(metadata.id.as_str(), to_save)?;
+        }
+        count
+    };
+    write.commit()?;
+    Ok(count)
 }
 
-/// Get [Metadata] from the library and deploy it
-pub fn lib_delegate_deploy(id: &str, path: &str) -> Result<(), LibraryError> {
-    let mut g = lib_get(id)?;
-    g.deploy(path)
+/// Get [Metadata] from the library and deploy it,
+/// trying the configured password candidates on an encrypted archive without a stored password
+pub fn lib_delegate_deploy(
+    id: &str,
+    path: &str,
+    method: DeployMethod,
+    monitor: &FlateMonitor,
+) -> Result<(), LibraryError> {
+    let before = lib_get(id)?;
+    let mut g = before.clone();
+    lib_internal_seal_candidates();
+    g.detect_password(config_get_clone()?.password_candidates(), monitor)
         .map_err(|e| LibraryError::DeploymentError(e, id.to_string()))?;
-    lib_add(g)
-}
-
-/// Get [Metadata] from the library and deploy it off
-pub fn lib_delegate_deploy_off(id: &str) -> Result<(), LibraryError> {
-    let mut g = lib_get(id)?;
-    match g.deploy_off() {
-        Ok(_) => lib_add(g),
-        Err(err) => match err {
-            MetadataError::InvalidOperation(_) => {
-                // Update the info
-                lib_add(g)?;
-                Err(LibraryError::DeploymentOffError(err, id.to_string()))
+    let result = g.deploy(path, method, monitor);
+    // A found password is worth keeping even if the deployment fails
+    lib_internal_merge_deploy(&before, g)?;
+    result.map_err(|e| LibraryError::DeploymentError(e, id.to_string()))
+}
+
+/// Write what a deployment changed from `before` into the entry as it is stored now,
+/// so edits made while deploying are kept: the added and removed deployments and a found password
+fn lib_internal_merge_deploy(before: &Metadata, after: Metadata) -> Result<(), LibraryError> {
+    let kept = |deployment: &Deployment, of: &Metadata| {
+        of.deployments.iter().any(|d| d.id == deployment.id)
+    };
+    let removed = before
+        .deployments
+        .iter()
+        .filter(|d| !kept(d, &after))
+        .map(|d| d.id.clone())
+        .collect::<HashSet<_>>();
+    let added = after
+        .deployments
+        .iter()
+        .filter(|d| !kept(d, before))
+        .cloned()
+        .collect::<Vec<Deployment>>();
+    let found = after
+        .archive_password
+        .filter(|_| before.archive_password.is_none());
+    if removed.is_empty() && added.is_empty() && found.is_none() {
+        return Ok(());
+    }
+    lib_internal_update(&before.id, |stored| {
+        stored.deployments.retain(|d| !removed.contains(&d.id));
+        stored.deployments.extend(added);
+        if stored.archive_password.is_none() {
+            stored.archive_password = found;
+        }
+        stored.mark_updated();
+    })?;
+    Ok(())
+}
+
+/// Get [Metadata] from the library and deploy it off, from a single deployment or all of them
+pub fn lib_delegate_deploy_off(
+    id: &str,
+    deployment: Option<&str>,
+    mode: DeployOffMode,
+) -> Result<Vec<DeployOffReport>, LibraryError> {
+    let before = lib_get(id)?;
+    let mut g = before.clone();
+    // Deployments removed before a failure are gone either way, update the info
+    let result = g.deploy_off(deployment, mode);
+    lib_internal_merge_deploy(&before, g)?;
+    result.map_err(|err| LibraryError::DeploymentOffError(err, id.to_string()))
+}
+
+/// Check every deployment of every entry, see [Metadata::check_deployment]
+pub fn lib_check_deployments() -> Result<Vec<DriftReport>, LibraryError> {
+    let mut reports = Vec::new();
+    for metadata in lib_get_all()?.entries() {
+        for deployment in &metadata.deployments {
+            reports.push(DriftReport {
+                id: metadata.id.clone(),
+                title: metadata.title.clone(),
+                deployment: deployment.id.clone(),
+                path: deployment.path.clone(),
+                status: metadata.check_deployment(deployment),
+            });
+        }
+    }
+    info!(
+        "Checked {} deployments, {} drifted",
+        reports.len(),
+        reports
+            .iter()
+            .filter(|r| !matches!(r.status, DriftStatus::Intact | DriftStatus::Unchecked))
+            .count()
+    );
+    Ok(reports)
+}
+
+/// Restore the missing or modified files of a deployment, see [Metadata::repair]
+pub fn lib_delegate_repair(
+    id: &str,
+    deployment: &str,
+    monitor: &FlateMonitor,
+) -> Result<DriftReport, LibraryError> {
+    let g = lib_get(id)?;
+    let status = g
+        .repair(deployment, monitor)
+        .map_err(|e| LibraryError::RepairError(e, id.to_string()))?;
+    Ok(DriftReport {
+        id: g.id.clone(),
+        title: g.title.clone(),
+        deployment: deployment.to_string(),
+        path: g
+            .deployments
+            .iter()
+            .find(|d| d.id == deployment)
+            .map(|d| d.path.clone())
+            .unwrap_or_default(),
+        status,
+    })
+}
+
+/// List the files inside the archive of an entry, see [Metadata::archive_contents]
+pub fn lib_archive_contents(id: &str) -> Result<Vec<ArchiveEntry>, LibraryError> {
+    lib_get(id)?
+        .archive_contents()
+        .map_err(|e| LibraryError::ContentsError(e, id.to_string()))
+}
+
+/// Read one page of a comic entry without deploying it, see [Metadata::comic_page]
+pub fn lib_comic_page(id: &str, index: usize) -> Result<ComicPage, LibraryError> {
+    lib_get(id)?
+        .comic_page(index)
+        .map_err(|e| LibraryError::ComicError(e, id.to_string()))
+}
+
+/// Rescan the pages of a comic entry and store the result, see [Metadata::scan_comic]
+pub fn lib_comic_scan(id: &str) -> Result<Metadata, LibraryError> {
+    let _artwork = artwork_lock();
+    let mut metadata = lib_get(id)?;
+    let had_cover = metadata.cover.is_some();
+    let scanned = metadata
+        .scan_comic()
+        .map_err(|e| LibraryError::ComicError(e, id.to_string()))?;
+    lib_internal_comic_cover(&mut metadata);
+    if scanned || metadata.cover.is_some() != had_cover {
+        lib_internal_add_nocheck(metadata.clone())?;
+    }
+    Ok(metadata)
+}
+
+/// Re-hash the archive of an entry, see [Metadata::verify].
+/// A hash calculated for an entry without one is recorded for later verifications
+pub fn lib_verify(id: &str) -> Result<VerifyReport, LibraryError> {
+    lib_internal_verify(lib_get(id)?)
+}
+
+/// Verify every entry in the library, reporting progress before each entry,
+/// stops with [LibraryError::Cancelled] when the callback breaks
+pub fn lib_verify_all(
+    mut on_progress: impl FnMut(&VerifyProgress) -> ControlFlow<()>,
+) -> Result<Vec<VerifyReport>, LibraryError> {
+    let entries = lib_get_all()?.into_entries();
+    let total = entries.len();
+    let mut reports = Vec::with_capacity(total);
+    for (index, metadata) in entries.into_iter().enumerate() {
+        if on_progress(&VerifyProgress {
+            current: index + 1,
+            total,
+            id: metadata.id.clone(),
+            title: metadata.title.clone(),
+        })
+        .is_break()
+        {
+            return Err(LibraryError::Cancelled);
+        }
+        reports.push(lib_internal_verify(metadata)?);
+    }
+    info!(
+        "Verified {} entries, {} not intact",
+        total,
+        reports
+            .iter()
+            .filter(|r| !matches!(r.status, VerifyStatus::Intact | VerifyStatus::NoArchive))
+            .count()
+    );
+    Ok(reports)
+}
+
+fn lib_internal_verify(metadata: Metadata) -> Result<VerifyReport, LibraryError> {
+    let status = metadata.verify();
+    let report = VerifyReport {
+        id: metadata.id.clone(),
+        title: metadata.title.clone(),
+        archive_path: metadata.archive_path.clone(),
+        status,
+    };
+    if let VerifyStatus::Unhashed(hash) = &report.status {
+        info!("Recording first hash of '{}': {}", &metadata.title, hash);
+        // Only the hash is written, the entry may have changed since it was read
+        let recorded = lib_internal_update(&metadata.id, |stored| {
+            if stored.archive_hash.is_none() && stored.archive_path == metadata.archive_path {
+                stored.archive_hash = Some(hash.clone());
             }
-            _ => Err(LibraryError::DeploymentOffError(err, id.to_string()))?,
-        },
+        });
+        match recorded {
+            Err(LibraryError::NotFound(_)) => warn!("'{}' was removed meanwhile", &metadata.title),
+            recorded => {
+                recorded?;
+            }
+        }
     }
+    Ok(report)
+}
+
+/// Outcome of verifying a single entry
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct VerifyReport {
+    pub id: String,
+    pub title: String,
+    pub archive_path: Option<String>,
+    pub status: VerifyStatus,
+}
+
+/// Outcome of checking a single deployment of an entry
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct DriftReport {
+    pub id: String,
+    pub title: String,
+    pub deployment: String,
+    pub path: String,
+    pub status: DriftStatus,
+}
+
+/// Sent by [lib_verify_all] before verifying each entry
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct VerifyProgress {
+    /// 1-based position of the entry being verified
+    pub current: usize,
+    pub total: usize,
+    pub id: String,
+    pub title: String,
 }
 
 /// Since the library has switched to [redb],
@@ -253,6 +987,16 @@ pub struct Library {
     entries: Vec<Metadata>,
 }
 
+impl Library {
+    pub fn entries(&self) -> &[Metadata] {
+        &self.entries
+    }
+
+    pub fn into_entries(self) -> Vec<Metadata> {
+        self.entries
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum LibraryError {
     #[error("Metadata with id {0} not found")]
@@ -267,6 +1011,9 @@ pub enum LibraryError {
     #[error("Failed to parse library file: {0}")]
     ParseError(bson::de::Error),
 
+    #[error("Duplicates existing entries: {}", .0.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(", "))]
+    Duplicate(Vec<Duplicate>),
+
     #[error("Failed to create metadata entry: {0}")]
     CreateError(MetadataError),
 
@@ -276,11 +1023,27 @@ pub enum LibraryError {
     #[error("Failed in deployment off for {1}: {0}")]
     DeploymentOffError(MetadataError, String),
 
+    #[error("Failed in repairing deployment for {1}: {0}")]
+    RepairError(MetadataError, String),
+
+    #[error("Failed to list archive contents for {1}: {0}")]
+    ContentsError(MetadataError, String),
+
+    #[error("Failed to read comic pages for {1}: {0}")]
+    ComicError(MetadataError, String),
+
+    #[error("Failed with images: {0}")]
+    ArtworkError(#[from] ArtworkError),
+
     #[error("Failed with config: {0}")]
     ConfigError(#[from] crate::foundation::config::ConfigError),
 
+    #[error("Failed with secrets: {0}")]
+    SecretError(#[from] SecretError),
+
+    /// Boxed as it is much larger than the other variants
     #[error("Failed in database opt: {0}")]
-    TransactionError(#[from] redb::TransactionError),
+    TransactionError(Box<redb::TransactionError>),
 
     #[error("Failed in database opt: {0}")]
     TableError(#[from] redb::TableError),
@@ -296,4 +1059,27 @@ pub enum LibraryError {
 
     #[error("Failed to acquire lock")]
     LockError,
+
+    #[error("Operation cancelled")]
+    Cancelled,
+}
+
+impl From<redb::TransactionError> for LibraryError {
+    fn from(err: redb::TransactionError) -> Self {
+        LibraryError::TransactionError(Box::new(err))
+    }
+}
+
+impl LibraryError {
+    /// Whether the failure was caused by cancelling through a [FlateMonitor]
+    pub fn is_cancelled(&self) -> bool {
+        match self {
+            LibraryError::CreateError(err)
+            | LibraryError::DeploymentError(err, _)
+            | LibraryError::DeploymentOffError(err, _)
+            | LibraryError::RepairError(err, _) => err.is_cancelled(),
+            LibraryError::Cancelled => true,
+            _ => false,
+        }
+    }
 }
@@ -1,16 +1,30 @@
-use crate::util::{file, flate};
-use bon::{Builder, builder};
+use crate::data::artwork::Artwork;
+use crate::data::comic::{self, ComicInfo, ComicPage};
+use crate::data::deploy::{
+    DeployDrift, DeployManifest, DeployMethod, DeployOffMode, DeployOffReport, Deployment,
+    DriftStatus,
+};
+use crate::foundation::secret::{Secret, SecretError};
+use crate::util::flate::{
+    ArchiveBackend, ArchiveEntry, ArchiveFormat, ArchiveOptions, FlateError, FlateMonitor,
+};
+use crate::util::volume::VolumeSet;
+use crate::util::{file, flate, hash};
+use bon::Builder;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::fs;
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Default)]
 pub enum ContentType {
+    #[default]
     Unknown,
     Game,
     Comic,
@@ -33,15 +47,10 @@ impl Display for ContentType {
     }
 }
 
-impl Default for ContentType {
-    fn default() -> Self {
-        ContentType::Unknown
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Default)]
 #[serde(tag = "platform", content = "id")]
 pub enum Platform {
+    #[default]
     Unknown,
     Steam,
     DLSite,
@@ -60,16 +69,28 @@ impl Display for Platform {
     }
 }
 
-impl Default for Platform {
-    fn default() -> Self {
-        Platform::Unknown
-    }
-}
-
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub enum DeployType {
     Directory,
+    /// A single file copied into the target directory, the deployed path is the copied file
     CopyFile,
+    /// The deployed path is a link to the archive directory
+    Symlink,
+    /// Hard links to the files of the archive directory
+    Hardlink,
+    /// Copy-on-write clones of the files of the archive directory
+    Reflink,
+}
+
+/// The single deployment stored by older versions, moved into [Metadata::deployments] by [Metadata::migrate]
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Default)]
+struct LegacyDeployment {
+    #[serde(default)]
+    deployed_path: Option<String>,
+    #[serde(default)]
+    deployed_type: Option<DeployType>,
+    #[serde(default)]
+    deployed_manifest: Option<DeployManifest>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
@@ -109,14 +130,35 @@ pub struct Metadata {
 
     #[serde(default)]
     pub archive_path: Option<String>,
+    /// Sealed once stored, see [Secret]
     #[serde(default)]
-    pub archive_password: Option<String>,
+    pub archive_password: Option<Secret>,
+    /// What wrote the archive, [None] unless it was created by [Metadata::new_on_create_archive]
     #[serde(default)]
-    pub deployed_path: Option<String>,
+    pub archive_backend: Option<ArchiveBackend>,
     #[serde(default)]
-    pub deployed_type: Option<DeployType>,
+    #[builder(default)]
+    pub deployments: Vec<Deployment>,
+    /// Only read from entries stored by older versions, see [LegacyDeployment]
+    #[serde(flatten, skip_serializing)]
+    #[builder(skip)]
+    legacy_deployment: LegacyDeployment,
     #[serde(default)]
     pub size_bytes: Option<u64>,
+    /// BLAKE3 hex digest of the archive file with all its volumes if split,
+    /// or of the directory tree if the archive is a directory
+    #[serde(default)]
+    pub archive_hash: Option<String>,
+
+    /// Pages of a [ContentType::Comic], see [Metadata::scan_comic]
+    #[serde(default)]
+    pub comic: Option<ComicInfo>,
+    /// Stored under the entry id, see [crate::data::artwork]
+    #[serde(default)]
+    pub cover: Option<Artwork>,
+    #[serde(default)]
+    #[builder(default)]
+    pub screenshots: Vec<Artwork>,
 
     #[serde(default)]
     #[builder(default)]
@@ -164,7 +206,8 @@ impl Metadata {
         platform_id: Option<String>,
         from_path: String,
         target_path: impl AsRef<Path>,
-        password: Option<String>,
+        options: &ArchiveOptions,
+        monitor: &FlateMonitor,
     ) -> Result<Self, MetadataError> {
         let from = Path::new(&from_path);
         if !from.is_dir() {
@@ -172,7 +215,7 @@ impl Metadata {
             return Err(MetadataError::InvalidOrigin(from_path));
         }
 
-        flate::compress_7z(from_path, &target_path, password.as_deref(), Some(9))
+        let backend = flate::compress(from_path, &target_path, options, monitor)
             .map_err(MetadataError::CompressionError)?;
 
         let mut metadata = Self::new(
@@ -182,14 +225,15 @@ impl Metadata {
             target_path.as_ref().to_string_lossy().to_string(),
         );
         let _ = metadata.calculate_size();
-        if let Some(pwd) = password {
-            metadata.archive_password = Some(pwd);
+        metadata.archive_backend = Some(backend);
+        if let Some(pwd) = options.password.clone() {
+            metadata.archive_password = Some(Secret::new(pwd));
         }
 
         Ok(metadata)
     }
 
-    /// Calculate the size of the archive
+    /// Calculate the size of the archive, cheap unlike [Metadata::calculate_hash]
     pub fn calculate_size(&mut self) -> Result<(), MetadataError> {
         match self.archive_path.as_ref() {
             None => {
@@ -209,9 +253,9 @@ impl Metadata {
                     error!(err);
                     Err(MetadataError::InvalidMetadata(err))
                 } else if path.is_file() {
-                    match path.metadata() {
-                        Ok(meta) => {
-                            let size = meta.len();
+                    // Every volume of a split archive counts
+                    match VolumeSet::find(path).and_then(|set| set.size()) {
+                        Ok(size) => {
                             info!("Calculated size of file {}: {}", path.display(), size);
                             self.size_bytes = Some(size);
                             Ok(())
@@ -252,19 +296,294 @@ impl Metadata {
         }
     }
 
+    /// Calculate the content hash of the archive, see [hash::hash_path]
+    pub fn calculate_hash(&mut self) -> Result<(), MetadataError> {
+        let Some(archive_path) = self.archive_path.as_ref() else {
+            return Ok(());
+        };
+        match hash::hash_path(archive_path) {
+            Ok(hash) => {
+                info!("Calculated hash of {}: {}", archive_path, hash);
+                self.archive_hash = Some(hash);
+                Ok(())
+            }
+            Err(err) => {
+                warn!("Failed to hash archive {}: {}", archive_path, err);
+                self.archive_hash = None;
+                Err(MetadataError::FileError(err))
+            }
+        }
+    }
+
+    /// Re-hash the archive and compare it with the recorded size and hash
+    pub fn verify(&self) -> VerifyStatus {
+        let Some(archive_path) = self.archive_path.as_ref() else {
+            return VerifyStatus::NoArchive;
+        };
+        let path = Path::new(archive_path);
+        if !path.exists() {
+            warn!("Archive of '{}' is missing: {}", &self.title, archive_path);
+            return VerifyStatus::Missing;
+        }
+
+        if path.is_file() {
+            match check_volumes(path) {
+                Ok(()) => {}
+                Err(MetadataError::MissingVolumes(missing)) => {
+                    return VerifyStatus::MissingVolumes(missing);
+                }
+                Err(err) => return VerifyStatus::Unreadable(err.to_string()),
+            }
+        }
+
+        if path.is_file()
+            && let Some(expected) = self.size_bytes
+        {
+            let actual = match VolumeSet::find(path).and_then(|set| set.size()) {
+                Ok(size) => size,
+                Err(err) => return VerifyStatus::Unreadable(err.to_string()),
+            };
+            if actual != expected {
+                warn!(
+                    "Archive of '{}' is resized from {} to {}",
+                    &self.title, expected, actual
+                );
+                return VerifyStatus::Resized { expected, actual };
+            }
+        }
+
+        match archive_format(path) {
+            Ok(_) => {}
+            Err(MetadataError::UnknownFormat(_)) => return VerifyStatus::UnknownFormat,
+            Err(err) => return VerifyStatus::Unreadable(err.to_string()),
+        }
+
+        let actual = match hash::hash_path(path) {
+            Ok(hash) => hash,
+            Err(err) => {
+                warn!("Failed to hash archive {}: {}", archive_path, err);
+                return VerifyStatus::Unreadable(err.to_string());
+            }
+        };
+        match self.archive_hash.as_ref() {
+            None => VerifyStatus::Unhashed(actual),
+            Some(expected) if expected == &actual => VerifyStatus::Intact,
+            Some(expected) => {
+                warn!(
+                    "Archive of '{}' is corrupted, expected hash {} but got {}",
+                    &self.title, expected, actual
+                );
+                VerifyStatus::Corrupted {
+                    expected: expected.clone(),
+                    actual,
+                }
+            }
+        }
+    }
+
+    /// Bring an entry stored by an older version up to date, returns whether anything changed.
+    ///
+    /// The single deployment of older versions becomes one of [Metadata::deployments],
+    /// a [DeployType::CopyFile] one used to record the target directory instead of the copied file
+    pub fn migrate(&mut self) -> bool {
+        let legacy = std::mem::take(&mut self.legacy_deployment);
+        let (Some(mut path), Some(deploy_type)) = (legacy.deployed_path, legacy.deployed_type)
+        else {
+            return false;
+        };
+
+        if deploy_type == DeployType::CopyFile
+            && let Some(archive_path) = self.archive_path.as_ref()
+            && let Some(file_name) = Path::new(archive_path).file_name()
+        {
+            let deployed = Path::new(&path);
+            if deployed.is_dir() || deployed.file_name() != Some(file_name) {
+                path = deployed.join(file_name).to_string_lossy().to_string();
+            }
+        }
+        info!("Migrating deployment of '{}' at {}", &self.title, path);
+        let mut deployment = Deployment::new(path, deploy_type, legacy.deployed_manifest);
+        deployment.date_deployed = self.date_updated;
+        self.deployments.push(deployment);
+        true
+    }
+
+    /// Encrypt a plain archive password, returns whether it was plain
+    pub fn seal_secrets(&mut self) -> Result<bool, SecretError> {
+        match self.archive_password.as_mut() {
+            Some(password) if !password.is_sealed() => {
+                password.seal()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Look for the password of an encrypted archive file among the candidates, if none is stored.
+    /// Returns whether one has been found and stored
+    pub fn detect_password(
+        &mut self,
+        candidates: &[Secret],
+        monitor: &FlateMonitor,
+    ) -> Result<bool, MetadataError> {
+        if self.archive_password.is_some() || candidates.is_empty() {
+            return Ok(false);
+        }
+        let archive_path = self.validate_archive_path()?.to_path_buf();
+        if !archive_format(&archive_path)?.is_some_and(ArchiveFormat::supports_password) {
+            return Ok(false);
+        }
+        // Candidates are only revealed for an archive that needs one
+        if !flate::is_encrypted(&archive_path).map_err(MetadataError::DecompressionError)? {
+            return Ok(false);
+        }
+
+        let candidates = candidates
+            .iter()
+            .map(Secret::reveal)
+            .collect::<Result<Vec<_>, _>>()?;
+        match flate::find_password(&archive_path, &candidates, monitor)
+            .map_err(MetadataError::DecompressionError)?
+        {
+            Some(password) => {
+                info!("Stored the found password of '{}'", &self.title);
+                self.archive_password = Some(Secret::new(password));
+                self.mark_updated();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// List the files deployment would place, from the archive itself without extracting it.
+    /// A directory archive lists its files, any other file lists itself
+    pub fn archive_contents(&self) -> Result<Vec<ArchiveEntry>, MetadataError> {
+        let archive_path = self.validate_archive_path()?;
+        if archive_format(archive_path)?.is_some() {
+            let password = self
+                .archive_password
+                .as_ref()
+                .map(Secret::reveal)
+                .transpose()?;
+            return flate::list_entries(archive_path, password.as_deref())
+                .map_err(MetadataError::DecompressionError);
+        }
+
+        let root = if archive_path.is_dir() {
+            archive_path
+        } else {
+            archive_path.parent().unwrap_or(archive_path)
+        };
+        let mut entries = Vec::new();
+        for entry in walkdir::WalkDir::new(archive_path).sort_by_file_name() {
+            let entry = entry.map_err(io::Error::from)?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let meta = entry.metadata().map_err(io::Error::from)?;
+            entries.push(ArchiveEntry {
+                path: entry
+                    .path()
+                    .strip_prefix(root)
+                    .unwrap_or(entry.path())
+                    .to_string_lossy()
+                    .replace('\\', "/"),
+                size: meta.len(),
+                compressed_size: None,
+                modified: meta.modified().ok().map(DateTime::<Utc>::from),
+                encrypted: false,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// The pages of the archive in reading order
+    pub fn comic_pages(&self) -> Result<Vec<String>, MetadataError> {
+        let entries = self.archive_contents()?;
+        Ok(comic::sort_pages(&entries)
+            .into_iter()
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Count the pages and find the cover of a [ContentType::Comic], clearing them for other types.
+    /// Returns whether anything changed
+    pub fn scan_comic(&mut self) -> Result<bool, MetadataError> {
+        let comic = if self.content_type == ContentType::Comic {
+            let pages = self.comic_pages()?;
+            pages.first().map(|cover| ComicInfo {
+                page_count: pages.len(),
+                cover: cover.clone(),
+            })
+        } else {
+            None
+        };
+        if comic == self.comic {
+            return Ok(false);
+        }
+        self.comic = comic;
+        self.mark_updated();
+        Ok(true)
+    }
+
+    /// Read one page, counted from 0, straight out of the archive without deploying it
+    pub fn comic_page(&self, index: usize) -> Result<ComicPage, MetadataError> {
+        let pages = self.comic_pages()?;
+        let Some(name) = pages.get(index) else {
+            return Err(MetadataError::InvalidOperation(format!(
+                "'{}' has no page {index} of {}",
+                &self.title,
+                pages.len()
+            )));
+        };
+        let archive_path = self.validate_archive_path()?;
+        let data = if archive_format(archive_path)?.is_some() {
+            let password = self
+                .archive_password
+                .as_ref()
+                .map(Secret::reveal)
+                .transpose()?;
+            flate::read_entry(archive_path, name, password.as_deref())
+                .map_err(MetadataError::DecompressionError)?
+        } else if archive_path.is_dir() {
+            fs::read(archive_path.join(name))?
+        } else {
+            fs::read(archive_path)?
+        };
+        Ok(ComicPage {
+            mime: comic::image_mime(name).unwrap_or("application/octet-stream"),
+            name: name.clone(),
+            data,
+        })
+    }
+
+    /// The cover followed by the screenshots
+    pub fn artworks(&self) -> impl Iterator<Item = &Artwork> {
+        self.cover.iter().chain(self.screenshots.iter())
+    }
+
     pub fn mark_updated(&mut self) {
         self.date_updated = Utc::now();
     }
 
-    fn remove_deploy_info(&mut self) {
-        self.deployed_path = None;
-        self.deployed_type = None;
+    /// Whether the entry is deployed anywhere
+    pub fn is_deployed(&self) -> bool {
+        !self.deployments.is_empty()
+    }
+
+    fn add_deployment(
+        &mut self,
+        path: String,
+        deploy_type: DeployType,
+        manifest: Option<DeployManifest>,
+    ) {
+        self.deployments
+            .push(Deployment::new(path, deploy_type, manifest));
         self.mark_updated();
     }
 
-    fn update_deployed_path(&mut self, path: String, deploy_type: DeployType) {
-        self.deployed_path = Some(path);
-        self.deployed_type = Some(deploy_type);
+    fn remove_deployment(&mut self, id: &str) {
+        self.deployments.retain(|deployment| deployment.id != id);
         self.mark_updated();
     }
 
@@ -332,90 +651,257 @@ impl Metadata {
         }
     }
 
-    pub fn deploy(&mut self, path: &str) -> Result<(), MetadataError> {
-        // Validation
-        let archive_path = self.validate_archive_path()?;
-        let deploy_path = self.validate_deploy_path(path)?;
-
-        if archive_path.is_dir() {
-            // Directory deployment
-            Self::check_target_empty(deploy_path, &self.title)?;
-
-            info!(
-                "Copying from {} to {}",
-                archive_path.display(),
-                deploy_path.display()
+    fn check_target_absent(path: &Path, title: &str) -> Result<(), MetadataError> {
+        if path.symlink_metadata().is_ok() {
+            let err = format!(
+                "Trying to deploy file '{}' over an existing file: {}",
+                title,
+                path.display()
             );
+            warn!(err);
+            Err(MetadataError::InvalidOperation(err))
+        } else {
+            Ok(())
+        }
+    }
 
-            file::copy_dir_all(archive_path, deploy_path)?;
-
-            self.update_deployed_path(path.to_string(), DeployType::Directory);
-        } else if archive_path.is_file() {
-            // File deployment
-            match archive_path
-                .extension()
-                .unwrap()
-                .to_string_lossy()
-                .to_lowercase()
-                .as_str()
-            {
-                "zip" => {
-                    Self::check_target_empty(deploy_path, &self.title)?;
-                    info!(
-                        "Decompressing ZIP {} to {}",
-                        archive_path.display(),
-                        deploy_path.display()
-                    );
-                    flate::decompress_zip(
-                        archive_path,
-                        deploy_path,
-                        self.archive_password.as_deref(),
-                    )
-                    .map_err(|e| MetadataError::DecompressionError(e.to_string()))?;
-                    self.update_deployed_path(path.to_string(), DeployType::Directory);
-                }
-                "rar" => {
-                    // Not fully implemented, but should work
-                    Self::check_target_empty(deploy_path, &self.title)?;
+    /// Extract or copy the archive into an empty directory
+    fn extract_to(
+        &self,
+        archive_path: &Path,
+        dst: &Path,
+        deploy_type: &DeployType,
+        monitor: &FlateMonitor,
+    ) -> Result<(), MetadataError> {
+        if archive_path.is_dir() {
+            match deploy_type {
+                DeployType::Hardlink => {
                     info!(
-                        "Decompressing RAR {} to {}",
+                        "Hard linking from {} to {}",
                         archive_path.display(),
-                        deploy_path.display()
+                        dst.display()
                     );
-                    flate::decompress_rar(
-                        archive_path,
-                        deploy_path,
-                        self.archive_password.as_deref(),
-                    )?;
-                    self.update_deployed_path(path.to_string(), DeployType::Directory);
+                    file::hard_link_dir_all(archive_path, dst)?;
                 }
-                "7z" => {
-                    Self::check_target_empty(deploy_path, &self.title)?;
+                DeployType::Reflink => {
                     info!(
-                        "Decompressing 7z {} to {}",
+                        "Cloning from {} to {}",
                         archive_path.display(),
-                        deploy_path.display()
+                        dst.display()
                     );
-                    flate::decompress_7z(
-                        archive_path,
-                        deploy_path,
-                        self.archive_password.as_deref(),
-                    )
-                    .map_err(MetadataError::DecompressionError)?;
-                    self.update_deployed_path(path.to_string(), DeployType::Directory);
+                    file::reflink_dir_all(archive_path, dst)?;
                 }
                 _ => {
                     info!(
-                        "Copying {} to {}",
+                        "Copying from {} to {}",
                         archive_path.display(),
-                        deploy_path.display()
+                        dst.display()
                     );
+                    file::copy_dir_all(archive_path, dst)?;
+                }
+            }
+            return Ok(());
+        }
+
+        let password = self
+            .archive_password
+            .as_ref()
+            .map(Secret::reveal)
+            .transpose()?;
+        let password = password.as_deref();
+        match archive_format(archive_path)? {
+            Some(ArchiveFormat::Zip) => {
+                info!(
+                    "Decompressing ZIP {} to {}",
+                    archive_path.display(),
+                    dst.display()
+                );
+                flate::decompress_zip(archive_path, dst, password, monitor)
+            }
+            Some(ArchiveFormat::Rar) => {
+                // Not fully implemented, but should work
+                info!(
+                    "Decompressing RAR {} to {}",
+                    archive_path.display(),
+                    dst.display()
+                );
+                flate::decompress_rar(archive_path, dst, password, monitor)
+            }
+            Some(ArchiveFormat::SevenZ) => {
+                info!(
+                    "Decompressing 7z {} to {}",
+                    archive_path.display(),
+                    dst.display()
+                );
+                flate::decompress_7z(archive_path, dst, password, monitor)
+            }
+            Some(
+                ArchiveFormat::Tar | ArchiveFormat::Gzip | ArchiveFormat::Zstd | ArchiveFormat::Xz,
+            ) => {
+                info!(
+                    "Decompressing tarball {} to {}",
+                    archive_path.display(),
+                    dst.display()
+                );
+                flate::decompress_tar(archive_path, dst, monitor)
+            }
+            None => {
+                return Err(MetadataError::UnknownFormat(
+                    archive_path.display().to_string(),
+                ));
+            }
+        }
+        .map_err(MetadataError::DecompressionError)
+    }
 
-                    let target_file_path = deploy_path.join(archive_path.file_name().unwrap());
-                    fs::copy(archive_path, &target_file_path)?;
-                    self.update_deployed_path(path.to_string(), DeployType::CopyFile);
+    /// Undo a failed deployment, returning the error with what has been rolled back
+    fn rollback(
+        &self,
+        err: MetadataError,
+        staging: &Path,
+        deploy_path: &Path,
+        created: bool,
+    ) -> MetadataError {
+        fn remove(path: &Path, what: &str, rolled_back: &mut Vec<String>) {
+            let result = if path.is_dir() {
+                fs::remove_dir_all(path)
+            } else {
+                fs::remove_file(path)
+            };
+            match result {
+                Ok(_) => rolled_back.push(format!("removed {what} {}", path.display())),
+                Err(err) => {
+                    error!("Failed to remove {what} {}: {}", path.display(), err);
+                    rolled_back.push(format!("failed to remove {what} {}", path.display()));
                 }
             }
+        }
+
+        let mut rolled_back = Vec::new();
+        if staging.exists() {
+            remove(staging, "staging", &mut rolled_back);
+        }
+        if created && deploy_path.exists() {
+            remove(deploy_path, "created target", &mut rolled_back);
+        }
+        if rolled_back.is_empty() {
+            rolled_back.push("nothing to remove".to_string());
+        }
+
+        let rolled_back = rolled_back.join(", ");
+        warn!(
+            "Rolled back deployment of '{}': {}",
+            &self.title, rolled_back
+        );
+        MetadataError::RolledBack(Box::new(err), rolled_back)
+    }
+
+    /// Deploy the archive to the given directory, reporting the decompression to the monitor.
+    /// A directory archive is placed by the given [DeployMethod], archive files are always extracted.
+    ///
+    /// The output is staged next to the target and moved into place once complete,
+    /// a failure removes the staged output and the target if it was created here
+    pub fn deploy(
+        &mut self,
+        path: &str,
+        method: DeployMethod,
+        monitor: &FlateMonitor,
+    ) -> Result<(), MetadataError> {
+        // Validation
+        let archive_path = self.validate_archive_path()?;
+        if method != DeployMethod::Copy && !archive_path.is_dir() {
+            let err = format!(
+                "Trying to deploy '{}' by {:?}, which needs a directory archive",
+                &self.title, method
+            );
+            warn!(err);
+            return Err(MetadataError::InvalidOperation(err));
+        }
+        if let Some(existing) = self.deployments.iter().find(|deployment| {
+            let deployed = Path::new(&deployment.path);
+            deployed == Path::new(path)
+                || (deployment.deploy_type == DeployType::CopyFile
+                    && deployed.parent() == Some(Path::new(path)))
+        }) {
+            let err = format!(
+                "Trying to deploy '{}' to {}, which already has deployment {}",
+                &self.title, path, existing.id
+            );
+            warn!(err);
+            return Err(MetadataError::InvalidOperation(err));
+        }
+        // Before the target is created, an unknown or incomplete archive has nothing to deploy
+        let format = archive_format(archive_path)?;
+        if format.is_some() {
+            check_volumes(archive_path)?;
+        }
+        let created = !Path::new(path).exists();
+        let deploy_path = self.validate_deploy_path(path)?;
+
+        if archive_path.is_file() && format.is_none() {
+            // File deployment
+            info!(
+                "Copying {} to {}",
+                archive_path.display(),
+                deploy_path.display()
+            );
+
+            let file_name = archive_path.file_name().unwrap().to_string_lossy();
+            let target_file_path = deploy_path.join(file_name.as_ref());
+            Self::check_target_absent(&target_file_path, &self.title)?;
+            let staging = deploy_path.join(format!(".{file_name}.staging"));
+            if let Err(err) = fs::copy(archive_path, &staging)
+                .and_then(|_| fs::rename(&staging, &target_file_path))
+            {
+                return Err(self.rollback(err.into(), &staging, deploy_path, created));
+            }
+            self.add_deployment(
+                target_file_path.to_string_lossy().to_string(),
+                DeployType::CopyFile,
+                None,
+            );
+        } else if method == DeployMethod::Symlink {
+            // Link deployment
+            Self::check_target_empty(deploy_path, &self.title)?;
+            info!(
+                "Linking {} to {}",
+                deploy_path.display(),
+                archive_path.display()
+            );
+
+            // A relative archive path would resolve against the link's directory
+            let link_target = std::path::absolute(archive_path)?;
+            fs::remove_dir(deploy_path)?;
+            if let Err(err) = file::symlink_dir(&link_target, deploy_path) {
+                // Nothing has been written, put the empty target back
+                if !created {
+                    fs::create_dir_all(deploy_path)?;
+                }
+                return Err(err.into());
+            }
+            self.add_deployment(path.to_string(), DeployType::Symlink, None);
+        } else if archive_path.is_dir() || archive_path.is_file() {
+            // Directory deployment
+            Self::check_target_empty(deploy_path, &self.title)?;
+
+            let deploy_type = match method {
+                DeployMethod::Hardlink => DeployType::Hardlink,
+                DeployMethod::Reflink => DeployType::Reflink,
+                _ => DeployType::Directory,
+            };
+            let staging = staging_path(deploy_path);
+            let manifest = self
+                .extract_to(archive_path, &staging, &deploy_type, monitor)
+                .and_then(|_| {
+                    let manifest = DeployManifest::scan(&staging)?;
+                    file::move_dir(&staging, deploy_path)?;
+                    Ok(manifest)
+                });
+            let manifest = match manifest {
+                Ok(manifest) => manifest,
+                Err(err) => return Err(self.rollback(err, &staging, deploy_path, created)),
+            };
+            self.add_deployment(path.to_string(), deploy_type, Some(manifest));
         } else {
             let err = format!(
                 "Unexpected path type for archive {}: {}",
@@ -431,34 +917,118 @@ impl Metadata {
         Ok(())
     }
 
-    pub fn deploy_off(&mut self) -> Result<(), MetadataError> {
-        fn err_invalid_path(m: &mut Metadata) -> Result<(), MetadataError> {
-            m.remove_deploy_info();
-            let err = format!(
-                "Trying to deploy off '{}' without a valid deployed path",
-                &m.title
+    /// Remove the files of the deployment with the given id, or of every deployment,
+    /// see [DeployOffMode] for files modified since deployment.
+    ///
+    /// Files not written by a deployment are left alone, unless it has no manifest recorded.
+    /// A deployment whose path is gone is dropped with an error, stopping at the first failure
+    pub fn deploy_off(
+        &mut self,
+        deployment: Option<&str>,
+        mode: DeployOffMode,
+    ) -> Result<Vec<DeployOffReport>, MetadataError> {
+        let targets = match deployment {
+            Some(id) => self
+                .deployments
+                .iter()
+                .filter(|deployment| deployment.id == id)
+                .cloned()
+                .collect::<Vec<_>>(),
+            None => self.deployments.clone(),
+        };
+        if targets.is_empty() {
+            let err = match deployment {
+                Some(id) => format!(
+                    "Trying to deploy off '{}' without deployment {}",
+                    &self.title, id
+                ),
+                None => format!(
+                    "Trying to deploy off '{}' which is not deployed",
+                    &self.title
+                ),
+            };
+            warn!(err);
+            return Err(MetadataError::InvalidOperation(err));
+        }
+
+        let mut reports = Vec::new();
+        for deployment in targets {
+            let Some(mut report) = self.remove_deployed(&deployment, mode)? else {
+                self.remove_deployment(&deployment.id);
+                let err = format!(
+                    "Trying to deploy off '{}' without a valid deployed path: {}",
+                    &self.title, deployment.path
+                );
+                error!(err);
+                return Err(MetadataError::InvalidOperation(err));
+            };
+            info!(
+                "Deployed off '{}' from {}, {} files removed",
+                &self.title,
+                deployment.path,
+                report.removed.len()
             );
-            error!(err);
-            Err(MetadataError::InvalidOperation(err))
+            report.deployment = deployment.id.clone();
+            report.path = deployment.path.clone();
+            self.remove_deployment(&deployment.id);
+            reports.push(report);
         }
+        Ok(reports)
+    }
 
-        match (&self.deployed_path, &self.deployed_type) {
-            (None, _) | (_, None) => {
-                return err_invalid_path(self);
+    /// Remove the files of a single deployment, [None] if its path is gone
+    fn remove_deployed(
+        &self,
+        deployment: &Deployment,
+        mode: DeployOffMode,
+    ) -> Result<Option<DeployOffReport>, MetadataError> {
+        let deploy_path = Path::new(&deployment.path);
+        let report = match deployment.deploy_type {
+            DeployType::Symlink => {
+                // The link itself, whether the archive is still there or not
+                if !deploy_path.is_symlink() {
+                    return Ok(None);
+                }
+                info!("Removing link {}", deploy_path.display());
+                file::remove_symlink_dir(deploy_path)?;
+                DeployOffReport {
+                    removed: vec![
+                        deploy_path
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .to_string(),
+                    ],
+                    ..Default::default()
+                }
+            }
+            _ if !deploy_path.exists() => {
+                return Ok(None);
             }
-            (Some(deploy_path), Some(deploy_type)) => {
-                let deploy_path = Path::new(deploy_path);
-                if !deploy_path.exists() {
-                    return err_invalid_path(self);
+            DeployType::Directory | DeployType::Hardlink | DeployType::Reflink => {
+                if !deploy_path.is_dir() {
+                    return Ok(None);
                 }
 
-                match deploy_type {
-                    DeployType::Directory => {
-                        if !deploy_path.is_dir() {
-                            return err_invalid_path(self);
-                        }
-                        info!("Clearing directory {}", deploy_path.display());
-
+                match deployment.manifest.as_ref() {
+                    Some(manifest) => {
+                        info!("Removing deployed files from {}", deploy_path.display());
+                        manifest.remove(deploy_path, mode)?
+                    }
+                    None if mode == DeployOffMode::KeepUserFiles => {
+                        let err = format!(
+                            "No manifest recorded for deployment of '{}', unable to tell user files apart",
+                            &self.title
+                        );
+                        warn!(err);
+                        return Err(MetadataError::InvalidOperation(err));
+                    }
+                    None => {
+                        warn!(
+                            "No manifest recorded, clearing directory {}",
+                            deploy_path.display()
+                        );
+                        let mut report = DeployOffReport::default();
                         for entry in fs::read_dir(deploy_path)? {
                             let entry = entry?;
                             if entry.path().is_dir() {
@@ -466,29 +1036,298 @@ impl Metadata {
                             } else {
                                 fs::remove_file(entry.path())?;
                             }
+                            report
+                                .removed
+                                .push(entry.file_name().to_string_lossy().to_string());
                         }
-
-                        info!("Successfully cleared directory {}", &deploy_path.display());
-
-                        self.remove_deploy_info();
+                        report
                     }
-                    DeployType::CopyFile => {
-                        if !deploy_path.is_file() {
-                            return err_invalid_path(self);
-                        }
-                        info!("Deleting file {}", deploy_path.display());
-                        fs::remove_file(deploy_path)?;
-                        info!("Successfully deleted file {}", &deploy_path.display());
+                }
+            }
+            DeployType::CopyFile => {
+                if !deploy_path.is_file() {
+                    return Ok(None);
+                }
+                info!("Deleting file {}", deploy_path.display());
+                fs::remove_file(deploy_path)?;
+                DeployOffReport {
+                    removed: vec![
+                        deploy_path
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .to_string(),
+                    ],
+                    ..Default::default()
+                }
+            }
+        };
+        Ok(Some(report))
+    }
 
-                        self.remove_deploy_info();
+    /// Compare a deployment with what was deployed, see [DriftStatus]
+    pub fn check_deployment(&self, deployment: &Deployment) -> DriftStatus {
+        let deploy_path = Path::new(&deployment.path);
+        let status = match deployment.deploy_type {
+            DeployType::Symlink => {
+                // A dangling link is as good as gone
+                if deploy_path.is_symlink() && deploy_path.is_dir() {
+                    DriftStatus::Intact
+                } else {
+                    DriftStatus::Missing
+                }
+            }
+            DeployType::CopyFile => {
+                if !deploy_path.is_file() {
+                    DriftStatus::Missing
+                } else if let Some(expected) = self.archive_hash.as_ref() {
+                    match hash::hash_file(deploy_path) {
+                        Ok(actual) if &actual == expected => DriftStatus::Intact,
+                        Ok(_) => DriftStatus::Modified(DeployDrift {
+                            modified: vec![
+                                deploy_path
+                                    .file_name()
+                                    .unwrap_or_default()
+                                    .to_string_lossy()
+                                    .to_string(),
+                            ],
+                            ..Default::default()
+                        }),
+                        Err(err) => DriftStatus::Unreadable(err.to_string()),
                     }
+                } else {
+                    DriftStatus::Unchecked
+                }
+            }
+            DeployType::Directory | DeployType::Hardlink | DeployType::Reflink => {
+                if !deploy_path.is_dir() {
+                    DriftStatus::Missing
+                } else if let Some(manifest) = deployment.manifest.as_ref() {
+                    manifest
+                        .check(deploy_path)
+                        .unwrap_or_else(|err| DriftStatus::Unreadable(err.to_string()))
+                } else {
+                    DriftStatus::Unchecked
+                }
+            }
+        };
+        if !matches!(status, DriftStatus::Intact | DriftStatus::Unchecked) {
+            warn!(
+                "Deployment of '{}' at {} has drifted: {:?}",
+                &self.title, deployment.path, status
+            );
+        }
+        status
+    }
+
+    /// Restore the missing or modified files of a deployment from the archive,
+    /// leaving the others alone, and return the state after repairing
+    pub fn repair(
+        &self,
+        deployment: &str,
+        monitor: &FlateMonitor,
+    ) -> Result<DriftStatus, MetadataError> {
+        let Some(deployment) = self.deployments.iter().find(|d| d.id == deployment) else {
+            let err = format!(
+                "Trying to repair '{}' without deployment {}",
+                &self.title, deployment
+            );
+            warn!(err);
+            return Err(MetadataError::InvalidOperation(err));
+        };
+        let files = match self.check_deployment(deployment) {
+            DriftStatus::Intact => return Ok(DriftStatus::Intact),
+            DriftStatus::Unchecked | DriftStatus::Unreadable(_) => {
+                let err = format!(
+                    "Trying to repair deployment of '{}' at {}, which could not be checked",
+                    &self.title, deployment.path
+                );
+                warn!(err);
+                return Err(MetadataError::InvalidOperation(err));
+            }
+            DriftStatus::Missing => deployment
+                .manifest
+                .as_ref()
+                .map(|manifest| manifest.files.iter().map(|f| f.path.clone()).collect())
+                .unwrap_or_default(),
+            DriftStatus::Partial(drift) | DriftStatus::Modified(drift) => drift
+                .missing
+                .into_iter()
+                .chain(drift.modified)
+                .collect::<Vec<_>>(),
+        };
+
+        let archive_path = self.validate_archive_path()?;
+        let deploy_path = Path::new(&deployment.path);
+        info!(
+            "Repairing deployment of '{}' at {}, {} files to restore",
+            &self.title,
+            deploy_path.display(),
+            files.len()
+        );
+        // Symlinks are recreated from the manifest, whatever the archive is
+        let links = deployment
+            .manifest
+            .iter()
+            .flat_map(|manifest| &manifest.files)
+            .filter_map(|file| Some((file.path.as_str(), file.link.as_deref()?)))
+            .collect::<HashMap<_, _>>();
+        let (restored_links, files) = files
+            .into_iter()
+            .partition::<Vec<_>, _>(|file| links.contains_key(file.as_str()));
+        for link in &restored_links {
+            restore_file(&deploy_path.join(link), |dst| {
+                file::symlink(links[link.as_str()], dst)
+            })?;
+        }
+        match deployment.deploy_type {
+            DeployType::Symlink => {
+                if deploy_path.is_symlink() {
+                    file::remove_symlink_dir(deploy_path)?;
+                }
+                file::symlink_dir(std::path::absolute(archive_path)?, deploy_path)?;
+            }
+            DeployType::CopyFile => {
+                fs::copy(archive_path, deploy_path)?;
+            }
+            _ if archive_path.is_dir() => {
+                for file in &files {
+                    let src = archive_path.join(file);
+                    restore_file(&deploy_path.join(file), |dst| {
+                        match deployment.deploy_type {
+                            DeployType::Hardlink => fs::hard_link(&src, dst),
+                            DeployType::Reflink => reflink_copy::reflink(&src, dst),
+                            _ => fs::copy(&src, dst).map(|_| ()),
+                        }
+                    })?;
+                }
+            }
+            // A zip reads a single file directly, so only the drifted ones are read out of it
+            _ if files.len() == 1 || archive_format(archive_path)? == Some(ArchiveFormat::Zip) => {
+                let password = self
+                    .archive_password
+                    .as_ref()
+                    .map(Secret::reveal)
+                    .transpose()?;
+                monitor.begin(None, Some(files.len() as u64));
+                for file in &files {
+                    monitor
+                        .enter(file.as_str())
+                        .map_err(MetadataError::DecompressionError)?;
+                    let data = flate::read_entry(archive_path, file, password.as_deref())
+                        .map_err(MetadataError::DecompressionError)?;
+                    restore_file(&deploy_path.join(file), |dst| fs::write(dst, &data))?;
+                    monitor.leave();
                 }
+                monitor.finish();
+            }
+            _ => {
+                // Reading files one by one decodes a solid 7z or a tarball from its start every
+                // time, extracting once costs a single pass
+                let staging = staging_path(deploy_path);
+                let restored = self
+                    .extract_to(archive_path, &staging, &DeployType::Directory, monitor)
+                    .and_then(|_| {
+                        for file in &files {
+                            let src = staging.join(file);
+                            restore_file(&deploy_path.join(file), |dst| {
+                                fs::rename(&src, dst).or_else(|_| fs::copy(&src, dst).map(|_| ()))
+                            })?;
+                        }
+                        Ok(())
+                    });
+                if staging.exists()
+                    && let Err(err) = fs::remove_dir_all(&staging)
+                {
+                    warn!("Failed to remove staging {}: {}", staging.display(), err);
+                }
+                restored?;
             }
         }
-        Ok(())
+
+        Ok(self.check_deployment(deployment))
+    }
+}
+
+/// Replace a deployed file with the one written by `place`, creating its parent directories
+fn restore_file(dst: &Path, place: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    if dst.is_symlink() {
+        fs::remove_file(dst).or_else(|_| file::remove_symlink_dir(dst))?;
+    } else if dst.is_file() {
+        fs::remove_file(dst)?;
+    }
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    place(dst)
+}
+
+/// Fail with [MetadataError::MissingVolumes] if the archive is split and some volumes are gone
+fn check_volumes(path: &Path) -> Result<(), MetadataError> {
+    let set = VolumeSet::find(path)?;
+    if set.missing.is_empty() {
+        return Ok(());
+    }
+    warn!("Missing volumes of {}: {:?}", path.display(), set.missing);
+    Err(MetadataError::MissingVolumes(
+        set.missing
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect(),
+    ))
+}
+
+/// Format of an archive file by its content, [None] for a directory or a plain file copied as is.
+/// A file named like an archive but holding none of the known formats fails with
+/// [MetadataError::UnknownFormat] instead of being copied
+fn archive_format(path: &Path) -> Result<Option<ArchiveFormat>, MetadataError> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    match ArchiveFormat::detect(path)? {
+        None if ArchiveFormat::has_archive_extension(path) => {
+            warn!("Unknown archive format of {}", path.display());
+            Err(MetadataError::UnknownFormat(path.display().to_string()))
+        }
+        format => Ok(format),
     }
 }
 
+/// A hidden directory next to the deploy target, on the same filesystem in most cases
+fn staging_path(deploy_path: &Path) -> PathBuf {
+    let name = deploy_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    deploy_path.with_file_name(format!(
+        ".{name}.staging-{}",
+        Utc::now().format("%Y%m%d-%H%M%S%3f")
+    ))
+}
+
+/// Result of re-hashing an archive, see [Metadata::verify]
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub enum VerifyStatus {
+    /// Size and hash match the recorded ones
+    Intact,
+    /// No hash was recorded before, holds the one just calculated
+    Unhashed(String),
+    /// The entry has no archive path
+    NoArchive,
+    /// The archive path does not exist anymore
+    Missing,
+    /// The archive exists but could not be read
+    Unreadable(String),
+    /// The archive file is named like an archive but holds none of the known formats
+    UnknownFormat,
+    /// Volumes of a split archive are gone, the last one is only noticed by the size
+    MissingVolumes(Vec<String>),
+    /// The archive file has a different size than recorded
+    Resized { expected: u64, actual: u64 },
+    /// Same size but a different hash than recorded
+    Corrupted { expected: String, actual: String },
+}
+
 #[derive(Debug, Error)]
 pub enum MetadataError {
     #[error("Invalid metadata: {0}")]
@@ -501,14 +1340,465 @@ pub enum MetadataError {
     FileError(#[from] std::io::Error),
 
     #[error("Failed to compress: {0}")]
-    CompressionError(String),
+    CompressionError(FlateError),
 
     #[error("Failed in decompressing: {0}")]
-    DecompressionError(String),
+    DecompressionError(FlateError),
 
-    #[error("Failed in decompressing RAR: {0}")]
-    DecompressionRARError(#[from] unrar::error::UnrarError),
+    #[error("Unknown archive format of {0}")]
+    UnknownFormat(String),
+
+    #[error("Missing archive volumes: {}", .0.join(", "))]
+    MissingVolumes(Vec<String>),
 
     #[error("Invalid operation: {0}")]
     InvalidOperation(String),
+
+    #[error("Failed to reveal the archive password: {0}")]
+    SecretError(#[from] SecretError),
+
+    #[error("{0}, rolled back: {1}")]
+    RolledBack(Box<MetadataError>, String),
+}
+
+impl MetadataError {
+    /// Whether the failure was caused by cancelling through a [FlateMonitor]
+    pub fn is_cancelled(&self) -> bool {
+        match self {
+            MetadataError::CompressionError(FlateError::Cancelled)
+            | MetadataError::DecompressionError(FlateError::Cancelled) => true,
+            MetadataError::RolledBack(err, _) => err.is_cancelled(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::file::cd_test_dir;
+    use crate::util::flate::CompressFormat;
+
+    #[test]
+    fn test_verify() {
+        let dir = cd_test_dir("verify");
+        let archive = dir.join("archive.bin");
+        fs::write(&archive, b"0123456789").unwrap();
+
+        let mut metadata = Metadata::new(
+            "Verify".to_string(),
+            Platform::Unknown,
+            None,
+            archive.to_string_lossy().to_string(),
+        );
+        assert!(matches!(metadata.verify(), VerifyStatus::Unhashed(_)));
+        metadata.calculate_size().unwrap();
+        metadata.calculate_hash().unwrap();
+        assert_eq!(metadata.verify(), VerifyStatus::Intact);
+
+        fs::write(&archive, b"0123456780").unwrap();
+        assert!(matches!(metadata.verify(), VerifyStatus::Corrupted { .. }));
+
+        fs::write(&archive, b"012").unwrap();
+        assert_eq!(
+            metadata.verify(),
+            VerifyStatus::Resized {
+                expected: 10,
+                actual: 3
+            }
+        );
+
+        fs::remove_file(&archive).unwrap();
+        assert_eq!(metadata.verify(), VerifyStatus::Missing);
+
+        // Split archives count every volume
+        fs::write(dir.join("split.7z.001"), b"7z\xBC\xAF\x27\x1Cfirst").unwrap();
+        fs::write(dir.join("split.7z.003"), b"third").unwrap();
+        let mut metadata = Metadata::new(
+            "Split".to_string(),
+            Platform::Unknown,
+            None,
+            dir.join("split.7z.001").to_string_lossy().to_string(),
+        );
+        let missing = dir.join("split.7z.002").to_string_lossy().to_string();
+        assert_eq!(
+            metadata.verify(),
+            VerifyStatus::MissingVolumes(vec![missing.clone()])
+        );
+        let result = metadata.deploy(
+            &dir.join("split_target").to_string_lossy(),
+            DeployMethod::Copy,
+            &FlateMonitor::silent(),
+        );
+        assert!(matches!(result, Err(MetadataError::MissingVolumes(_))));
+        assert!(!dir.join("split_target").exists());
+
+        fs::write(&missing, b"second").unwrap();
+        metadata.calculate_size().unwrap();
+        metadata.calculate_hash().unwrap();
+        assert_eq!(metadata.size_bytes, Some(11 + 6 + 5));
+        assert_eq!(metadata.verify(), VerifyStatus::Intact);
+        fs::remove_file(dir.join("split.7z.003")).unwrap();
+        assert!(matches!(metadata.verify(), VerifyStatus::Resized { .. }));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_copy_file_deploy() {
+        let dir = cd_test_dir("copy_file_deploy");
+        let archive = dir.join("tool.exe");
+        fs::write(&archive, b"tool").unwrap();
+        let target = dir.join("target");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("other.txt"), b"other").unwrap();
+
+        let mut metadata = Metadata::new(
+            "Tool".to_string(),
+            Platform::Unknown,
+            None,
+            archive.to_string_lossy().to_string(),
+        );
+        metadata
+            .deploy(
+                &target.to_string_lossy(),
+                DeployMethod::Copy,
+                &FlateMonitor::silent(),
+            )
+            .unwrap();
+        let copied = target.join("tool.exe");
+        assert_eq!(metadata.deployments.len(), 1);
+        assert_eq!(metadata.deployments[0].deploy_type, DeployType::CopyFile);
+        assert_eq!(metadata.deployments[0].path, copied.to_string_lossy());
+        assert!(copied.is_file());
+        assert!(!metadata.migrate());
+
+        metadata.deploy_off(None, DeployOffMode::default()).unwrap();
+        assert!(!copied.exists());
+        assert!(target.join("other.txt").is_file());
+        assert!(!metadata.is_deployed());
+
+        // A file of the user with the same name is never overwritten
+        fs::write(&copied, b"user").unwrap();
+        let err = metadata
+            .deploy(
+                &target.to_string_lossy(),
+                DeployMethod::Copy,
+                &FlateMonitor::silent(),
+            )
+            .unwrap_err();
+        assert!(matches!(err, MetadataError::InvalidOperation(_)));
+        assert_eq!(fs::read(&copied).unwrap(), b"user");
+        assert!(!metadata.is_deployed());
+        fs::remove_file(&copied).unwrap();
+
+        // Stored by an older version, a single deployment pointing at the directory
+        fs::copy(&archive, &copied).unwrap();
+        let legacy = bson::doc! {
+            "title": "Tool",
+            "archive_path": archive.to_string_lossy().to_string(),
+            "deployed_path": target.to_string_lossy().to_string(),
+            "deployed_type": "CopyFile",
+        };
+        let mut metadata = bson::from_document::<Metadata>(legacy).unwrap();
+        assert!(!metadata.is_deployed());
+        assert!(metadata.migrate());
+        assert_eq!(metadata.deployments.len(), 1);
+        assert_eq!(metadata.deployments[0].path, copied.to_string_lossy());
+        assert!(!metadata.migrate());
+        let stored = bson::to_document(&metadata).unwrap();
+        assert!(!stored.contains_key("deployed_path"));
+        metadata.deploy_off(None, DeployOffMode::default()).unwrap();
+        assert!(!copied.exists());
+
+        // Named like an archive without being one, never copied in its place
+        let broken = dir.join("broken.7z");
+        fs::write(&broken, b"not an archive").unwrap();
+        let mut metadata = Metadata::new(
+            "Broken".to_string(),
+            Platform::Unknown,
+            None,
+            broken.to_string_lossy().to_string(),
+        );
+        let target = dir.join("broken_target");
+        let result = metadata.deploy(
+            &target.to_string_lossy(),
+            DeployMethod::Copy,
+            &FlateMonitor::silent(),
+        );
+        assert!(matches!(result, Err(MetadataError::UnknownFormat(_))));
+        assert!(!target.exists());
+        assert_eq!(metadata.verify(), VerifyStatus::UnknownFormat);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_deploy_rollback() {
+        let dir = cd_test_dir("deploy_rollback");
+        let archive = dir.join("archive");
+        fs::create_dir_all(archive.join("sub")).unwrap();
+        fs::write(archive.join("a.txt"), b"a").unwrap();
+        fs::write(archive.join("sub/b.txt"), b"b").unwrap();
+
+        let mut metadata = Metadata::new(
+            "Rollback".to_string(),
+            Platform::Unknown,
+            None,
+            archive.to_string_lossy().to_string(),
+        );
+        let target = dir.join("target");
+        let monitor = FlateMonitor::silent();
+        metadata
+            .deploy(&target.to_string_lossy(), DeployMethod::Copy, &monitor)
+            .unwrap();
+        assert!(target.join("sub/b.txt").is_file());
+        assert_eq!(metadata.deployments[0].deploy_type, DeployType::Directory);
+
+        // A second deployment, then deploy off only the first one
+        let second = dir.join("second");
+        metadata
+            .deploy(&second.to_string_lossy(), DeployMethod::Copy, &monitor)
+            .unwrap();
+        assert!(
+            metadata
+                .deploy(&second.to_string_lossy(), DeployMethod::Copy, &monitor)
+                .is_err()
+        );
+        let first = metadata.deployments[0].id.clone();
+        let reports = metadata
+            .deploy_off(Some(&first), DeployOffMode::default())
+            .unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].deployment, first);
+        assert_eq!(reports[0].removed, vec!["a.txt", "sub/b.txt"]);
+        assert!(!target.join("sub").exists());
+        assert!(second.join("sub/b.txt").is_file());
+        assert_eq!(metadata.deployments.len(), 1);
+        metadata.deploy_off(None, DeployOffMode::default()).unwrap();
+        assert!(!metadata.is_deployed());
+
+        // A dangling link makes the copy fail after the first files
+        std::os::unix::fs::symlink(dir.join("nowhere"), archive.join("z.lnk")).unwrap();
+        let result = metadata.deploy(
+            &dir.join("new").to_string_lossy(),
+            DeployMethod::Copy,
+            &monitor,
+        );
+        assert!(matches!(result, Err(MetadataError::RolledBack(..))));
+        assert!(!dir.join("new").exists());
+        let result = metadata.deploy(&target.to_string_lossy(), DeployMethod::Copy, &monitor);
+        assert!(matches!(result, Err(MetadataError::RolledBack(..))));
+        assert_eq!(fs::read_dir(&target).unwrap().count(), 0);
+        assert!(!metadata.is_deployed());
+
+        // Nothing is left next to the targets
+        let names = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        assert!(
+            names.iter().all(|name| !name.contains("staging")),
+            "{names:?}"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_link_deploy() {
+        let dir = cd_test_dir("link_deploy");
+        let archive = dir.join("archive");
+        fs::create_dir_all(archive.join("sub")).unwrap();
+        fs::write(archive.join("a.txt"), b"a").unwrap();
+        fs::write(archive.join("sub/b.txt"), b"b").unwrap();
+
+        let mut metadata = Metadata::new(
+            "Link".to_string(),
+            Platform::Unknown,
+            None,
+            archive.to_string_lossy().to_string(),
+        );
+        let target = dir.join("target");
+        let monitor = FlateMonitor::silent();
+
+        fs::create_dir_all(&target).unwrap();
+        metadata
+            .deploy(&target.to_string_lossy(), DeployMethod::Symlink, &monitor)
+            .unwrap();
+        assert_eq!(metadata.deployments[0].deploy_type, DeployType::Symlink);
+        assert!(target.is_symlink());
+        assert!(target.join("sub/b.txt").is_file());
+        metadata.deploy_off(None, DeployOffMode::default()).unwrap();
+        assert!(!target.exists());
+        assert!(archive.join("sub/b.txt").is_file());
+
+        metadata
+            .deploy(&target.to_string_lossy(), DeployMethod::Hardlink, &monitor)
+            .unwrap();
+        assert_eq!(metadata.deployments[0].deploy_type, DeployType::Hardlink);
+        fs::write(target.join("save.dat"), b"save").unwrap();
+        let reports = metadata.deploy_off(None, DeployOffMode::default()).unwrap();
+        assert_eq!(reports[0].removed, vec!["a.txt", "sub/b.txt"]);
+        assert_eq!(reports[0].added, vec!["save.dat"]);
+        assert!(archive.join("a.txt").is_file());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_detect_password() {
+        let dir = cd_test_dir("detect_password");
+        let input = dir.join("input");
+        fs::create_dir_all(&input).unwrap();
+        fs::write(input.join("a.txt"), b"a").unwrap();
+        let zip = dir.join("locked.zip");
+        let options = ArchiveOptions {
+            password: Some("known".to_string()),
+            ..Default::default()
+        };
+        flate::compress_zip(&input, &zip, &options, &FlateMonitor::silent()).unwrap();
+
+        let mut metadata = Metadata::new(
+            "Locked".to_string(),
+            Platform::Unknown,
+            None,
+            zip.to_string_lossy().to_string(),
+        );
+        let candidates = [Secret::new("wrong"), Secret::new("known")];
+        assert!(
+            metadata
+                .detect_password(&candidates[..1], &FlateMonitor::silent())
+                .is_err()
+        );
+        assert!(metadata.archive_password.is_none());
+        assert!(
+            metadata
+                .detect_password(&candidates, &FlateMonitor::silent())
+                .unwrap()
+        );
+        assert_eq!(
+            metadata
+                .archive_password
+                .as_ref()
+                .unwrap()
+                .reveal()
+                .unwrap(),
+            "known"
+        );
+
+        // A candidate that can't be revealed doesn't stop an archive without encryption
+        let plain_zip = dir.join("plain.zip");
+        flate::compress_zip(
+            &input,
+            &plain_zip,
+            &ArchiveOptions::default(),
+            &FlateMonitor::silent(),
+        )
+        .unwrap();
+        let mut metadata = Metadata::new(
+            "Plain".to_string(),
+            Platform::Unknown,
+            None,
+            plain_zip.to_string_lossy().to_string(),
+        );
+        let broken = [Secret::new("enc:v1:broken")];
+        assert!(broken[0].reveal().is_err());
+        assert!(
+            !metadata
+                .detect_password(&broken, &FlateMonitor::silent())
+                .unwrap()
+        );
+        let target = dir.join("target");
+        metadata
+            .deploy(
+                &target.to_string_lossy(),
+                DeployMethod::Copy,
+                &FlateMonitor::silent(),
+            )
+            .unwrap();
+        assert!(target.join("a.txt").is_file());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_deploy_repair() {
+        let dir = cd_test_dir("deploy_repair");
+        let archive = dir.join("archive");
+        fs::create_dir_all(archive.join("sub")).unwrap();
+        fs::write(archive.join("a.txt"), b"a").unwrap();
+        fs::write(archive.join("sub/b.txt"), b"b").unwrap();
+        fs::write(archive.join("c.txt"), b"c").unwrap();
+
+        let mut metadata = Metadata::new(
+            "Repair".to_string(),
+            Platform::Unknown,
+            None,
+            archive.to_string_lossy().to_string(),
+        );
+        let target = dir.join("target");
+        let monitor = FlateMonitor::silent();
+        metadata
+            .deploy(&target.to_string_lossy(), DeployMethod::Copy, &monitor)
+            .unwrap();
+        let deployment = metadata.deployments[0].clone();
+        assert_eq!(metadata.check_deployment(&deployment), DriftStatus::Intact);
+
+        fs::remove_dir_all(target.join("sub")).unwrap();
+        fs::write(target.join("a.txt"), b"changed").unwrap();
+        fs::write(target.join("save.dat"), b"save").unwrap();
+        assert_eq!(
+            metadata.check_deployment(&deployment),
+            DriftStatus::Partial(DeployDrift {
+                missing: vec!["sub/b.txt".to_string()],
+                modified: vec!["a.txt".to_string()],
+            })
+        );
+
+        let status = metadata.repair(&deployment.id, &monitor).unwrap();
+        assert_eq!(status, DriftStatus::Intact);
+        assert_eq!(fs::read(target.join("a.txt")).unwrap(), b"a");
+        assert!(target.join("sub/b.txt").is_file());
+        assert!(target.join("save.dat").is_file());
+
+        fs::remove_dir_all(&target).unwrap();
+        assert_eq!(metadata.check_deployment(&deployment), DriftStatus::Missing);
+        metadata.repair(&deployment.id, &monitor).unwrap();
+        assert!(target.join("c.txt").is_file());
+
+        // Drifted files are read out of a zip one by one, a tarball is extracted once
+        let zip = dir.join("archive.zip");
+        flate::compress_zip(&archive, &zip, &ArchiveOptions::default(), &monitor).unwrap();
+        let tarball = dir.join("archive.tar.gz");
+        flate::compress_tar(&archive, &tarball, CompressFormat::TarGz, None, &monitor).unwrap();
+        for (case, packed) in [("zip", &zip), ("tarball", &tarball)] {
+            let mut metadata = Metadata::new(
+                format!("Repair {case}"),
+                Platform::Unknown,
+                None,
+                packed.to_string_lossy().to_string(),
+            );
+            let target = dir.join(format!("target_{case}"));
+            metadata
+                .deploy(&target.to_string_lossy(), DeployMethod::Copy, &monitor)
+                .unwrap();
+            let deployment = metadata.deployments[0].clone();
+            fs::remove_file(target.join("sub/b.txt")).unwrap();
+            fs::write(target.join("a.txt"), b"changed").unwrap();
+            let status = metadata.repair(&deployment.id, &monitor).unwrap();
+            assert_eq!(status, DriftStatus::Intact, "{case}");
+            assert_eq!(fs::read(target.join("a.txt")).unwrap(), b"a");
+            assert_eq!(fs::read(target.join("sub/b.txt")).unwrap(), b"b");
+            assert_eq!(
+                fs::read_dir(&dir).unwrap().count(),
+                if case == "zip" { 5 } else { 6 },
+                "{case}: staging left behind"
+            );
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,2 +1,9 @@
+pub mod artwork;
+pub mod comic;
+pub mod deploy;
+pub mod duplicate;
+mod index;
+pub mod job;
 pub mod library;
 pub mod metadata;
+pub mod query;
@@ -0,0 +1,276 @@
+use crate::data::metadata::{ContentType, Metadata, Platform};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// Conditions an entry must satisfy to be returned by a query, unset fields match everything
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Default)]
+#[serde(default)]
+pub struct LibraryFilter {
+    /// Case-insensitive substring of `title` or `original_title`
+    pub title: Option<String>,
+    pub platform: Option<Platform>,
+    pub content_type: Option<ContentType>,
+    /// Case-insensitive tag name
+    pub tag_name: Option<String>,
+    /// Case-insensitive tag category
+    pub tag_category: Option<String>,
+    pub deployed: Option<bool>,
+    pub size_min: Option<u64>,
+    pub size_max: Option<u64>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Default)]
+pub enum SortField {
+    #[default]
+    Title,
+    Platform,
+    Size,
+    DateCreated,
+    DateUpdated,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Default)]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Default)]
+#[serde(default)]
+pub struct LibrarySort {
+    pub field: SortField,
+    pub order: SortOrder,
+}
+
+/// A filtered, sorted and paginated request against the library
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Default)]
+#[serde(default)]
+pub struct LibraryQuery {
+    pub filter: LibraryFilter,
+    pub sort: Option<LibrarySort>,
+    pub offset: usize,
+    /// Page size, [None] for all remaining entries
+    pub limit: Option<usize>,
+}
+
+/// One page of query results, `total` counts all matching entries before pagination
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Default)]
+pub struct LibraryPage {
+    pub entries: Vec<Metadata>,
+    pub total: usize,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+/// Case folding shared by the filters and the tag index, Unicode-aware unlike ASCII comparisons
+pub(crate) fn fold_case(text: &str) -> String {
+    text.to_lowercase()
+}
+
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    fold_case(haystack).contains(&fold_case(needle))
+}
+
+impl LibraryFilter {
+    /// Check if the given [Metadata] satisfies every condition set in this filter
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        if let Some(title) = self.title.as_deref()
+            && !contains_ignore_case(&metadata.title, title)
+            && !metadata
+                .original_title
+                .as_deref()
+                .is_some_and(|t| contains_ignore_case(t, title))
+        {
+            return false;
+        }
+        if self
+            .platform
+            .as_ref()
+            .is_some_and(|p| p != &metadata.platform)
+        {
+            return false;
+        }
+        if self
+            .content_type
+            .as_ref()
+            .is_some_and(|c| c != &metadata.content_type)
+        {
+            return false;
+        }
+        if (self.tag_name.is_some() || self.tag_category.is_some())
+            && !metadata.tags.iter().any(|tag| {
+                self.tag_name
+                    .as_deref()
+                    .is_none_or(|name| fold_case(&tag.name) == fold_case(name))
+                    && self.tag_category.as_deref().is_none_or(|category| {
+                        tag.category
+                            .as_deref()
+                            .is_some_and(|c| fold_case(c) == fold_case(category))
+                    })
+            })
+        {
+            return false;
+        }
+        if self.deployed.is_some_and(|d| d != metadata.is_deployed()) {
+            return false;
+        }
+        if self.size_min.is_some() || self.size_max.is_some() {
+            let Some(size) = metadata.size_bytes else {
+                return false;
+            };
+            if self.size_min.is_some_and(|min| size < min)
+                || self.size_max.is_some_and(|max| size > max)
+            {
+                return false;
+            }
+        }
+        if self
+            .created_after
+            .is_some_and(|d| metadata.date_created < d)
+            || self
+                .created_before
+                .is_some_and(|d| metadata.date_created > d)
+            || self
+                .updated_after
+                .is_some_and(|d| metadata.date_updated < d)
+            || self
+                .updated_before
+                .is_some_and(|d| metadata.date_updated > d)
+        {
+            return false;
+        }
+        true
+    }
+}
+
+impl LibrarySort {
+    pub fn compare(&self, a: &Metadata, b: &Metadata) -> Ordering {
+        let ordering = match self.field {
+            SortField::Title => a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+            SortField::Platform => a
+                .platform
+                .to_string()
+                .cmp(&b.platform.to_string())
+                .then_with(|| a.platform_id.cmp(&b.platform_id)),
+            SortField::Size => a.size_bytes.cmp(&b.size_bytes),
+            SortField::DateCreated => a.date_created.cmp(&b.date_created),
+            SortField::DateUpdated => a.date_updated.cmp(&b.date_updated),
+        };
+        match self.order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+    }
+}
+
+impl LibraryQuery {
+    /// Sort and paginate already filtered entries
+    pub fn paginate(&self, mut entries: Vec<Metadata>) -> LibraryPage {
+        if let Some(sort) = self.sort.as_ref() {
+            entries.sort_by(|a, b| sort.compare(a, b));
+        }
+        let total = entries.len();
+        let entries = entries
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect();
+        LibraryPage {
+            entries,
+            total,
+            offset: self.offset,
+            limit: self.limit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::metadata::Tag;
+
+    fn sample(title: &str, platform: Platform, size: u64) -> Metadata {
+        let mut metadata = Metadata::new(title.to_string(), platform, None, "a.7z".to_string());
+        metadata.size_bytes = Some(size);
+        metadata
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut game = sample("Some Game", Platform::Steam, 100);
+        game.content_type = ContentType::Game;
+        game.tags.push(Tag {
+            name: "RPG".to_string(),
+            category: Some("Genre".to_string()),
+        });
+        let mut comic = sample("漫画", Platform::DLSite, 2000);
+        comic.tags.push(Tag {
+            name: "Ärger".to_string(),
+            category: None,
+        });
+
+        let filter = LibraryFilter {
+            title: Some("game".to_string()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&game));
+        assert!(!filter.matches(&comic));
+
+        let filter = LibraryFilter {
+            tag_name: Some("rpg".to_string()),
+            tag_category: Some("genre".to_string()),
+            content_type: Some(ContentType::Game),
+            ..Default::default()
+        };
+        assert!(filter.matches(&game));
+        assert!(!filter.matches(&comic));
+
+        // Same folding as the tag index, beyond ASCII
+        let filter = LibraryFilter {
+            tag_name: Some("ärger".to_string()),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&game));
+        assert!(filter.matches(&comic));
+
+        let filter = LibraryFilter {
+            platform: Some(Platform::DLSite),
+            size_min: Some(1000),
+            deployed: Some(false),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&game));
+        assert!(filter.matches(&comic));
+    }
+
+    #[test]
+    fn test_paginate() {
+        let entries = (0..5)
+            .map(|i| sample(&format!("Title {i}"), Platform::Unknown, i))
+            .collect::<Vec<_>>();
+        let query = LibraryQuery {
+            sort: Some(LibrarySort {
+                field: SortField::Size,
+                order: SortOrder::Desc,
+            }),
+            offset: 1,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = query.paginate(entries);
+        assert_eq!(page.total, 5);
+        assert_eq!(
+            page.entries
+                .iter()
+                .map(|e| e.size_bytes.unwrap())
+                .collect::<Vec<_>>(),
+            vec![3, 2]
+        );
+    }
+}
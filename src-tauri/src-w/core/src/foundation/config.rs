@@ -1,4 +1,6 @@
+use crate::foundation::secret::Secret;
 use crate::util::file::cd_with;
+use crate::util::flate::SevenZBackend;
 use config::{Config, FileFormat};
 use serde::{Deserialize, Serialize};
 use std::fs;
@@ -29,12 +31,33 @@ pub enum ConfigError {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AppConfig {
     data_dir: String,
+    /// Maximum amount of background jobs running at the same time
+    #[serde(default = "config_default_job_concurrency")]
+    job_concurrency: usize,
+    /// Known passwords tried on an encrypted archive without a stored one,
+    /// plain ones written by hand are sealed on the next deployment
+    #[serde(default)]
+    password_candidates: Vec<Secret>,
+    /// Which implementation handles 7z archives
+    #[serde(default)]
+    sevenz_backend: SevenZBackend,
+    /// The 7z executable, looked up in the system path if not set
+    #[serde(default)]
+    sevenz_path: Option<String>,
+}
+
+fn config_default_job_concurrency() -> usize {
+    2
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         AppConfig {
             data_dir: "data".to_string(),
+            job_concurrency: config_default_job_concurrency(),
+            password_candidates: Vec::new(),
+            sevenz_backend: SevenZBackend::default(),
+            sevenz_path: None,
         }
     }
 }
@@ -48,6 +71,26 @@ impl AppConfig {
         cd_with(&self.data_dir)
     }
 
+    pub fn job_concurrency(&self) -> usize {
+        self.job_concurrency.max(1)
+    }
+
+    pub fn password_candidates(&self) -> &[Secret] {
+        &self.password_candidates
+    }
+
+    pub fn sevenz_backend(&self) -> SevenZBackend {
+        self.sevenz_backend
+    }
+
+    /// The 7z executable to run, `7z` from the system path unless set
+    pub fn sevenz_program(&self) -> PathBuf {
+        self.sevenz_path
+            .as_deref()
+            .filter(|path| !path.is_empty())
+            .map_or_else(|| PathBuf::from("7z"), PathBuf::from)
+    }
+
     pub fn check(&self) -> anyhow::Result<()> {
         if !self.data_dir().exists() {
             debug!(
@@ -130,8 +173,19 @@ pub fn init_once_only() -> anyhow::Result<()> {
     get_clone()?.check()
 }
 
+/// Replace the password candidates and save the config
+pub fn set_password_candidates(candidates: Vec<Secret>) -> Result<(), ConfigError> {
+    config()
+        .write()
+        .map_err(|e| {
+            error!("Failed to acquire write lock: {e}");
+            ConfigError::LockError
+        })?
+        .password_candidates = candidates;
+    save()
+}
+
 /// Save the config to the default file
-#[allow(dead_code)]
 fn save() -> Result<(), ConfigError> {
     let config_c = get()?.clone();
     let config_path = cd_with("config.toml");
@@ -0,0 +1,348 @@
+use crate::foundation::config::{ConfigError, get_data_dir};
+use argon2::Argon2;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Debug, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, PoisonError, RwLock};
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// Prefix of a sealed secret, followed by the base64 of the nonce and the ciphertext
+const SEALED_PREFIX: &str = "enc:v1:";
+/// Random key in the data directory, used while no master passphrase is set
+const KEY_FILE: &str = "secret.key";
+/// Salt of the master passphrase, the key is derived on [secret_unlock]
+const PASSPHRASE_FILE: &str = "secret.json";
+/// Sealed with the derived key to tell a wrong passphrase apart
+const PASSPHRASE_CHECK: &str = "meta-manager";
+const NONCE_LEN: usize = 12;
+
+type SecretKey = [u8; 32];
+
+#[derive(Debug, Error)]
+pub enum SecretError {
+    #[error("Failed to read/write secret files: {0}")]
+    FileError(#[from] std::io::Error),
+
+    #[error("Failed with config: {0}")]
+    ConfigError(#[from] ConfigError),
+
+    #[error("Failed to parse passphrase file: {0}")]
+    ParseError(#[from] serde_json::Error),
+
+    #[error("Secrets are locked, unlock them with the master passphrase first")]
+    Locked,
+
+    #[error("No master passphrase is set")]
+    NoPassphrase,
+
+    #[error("Wrong master passphrase")]
+    WrongPassphrase,
+
+    #[error("Invalid secret key: {0}")]
+    InvalidKey(String),
+
+    #[error("Failed to encrypt or decrypt a secret")]
+    CryptoError,
+}
+
+/// A password kept encrypted with the key of the data directory, see [Secret::reveal].
+///
+/// Serialized as the sealed string, a plain one only lives until the entry is stored
+#[derive(Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+impl Secret {
+    /// A plain secret, sealed once stored
+    pub fn new(plain: impl Into<String>) -> Self {
+        Self(plain.into())
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        self.0.starts_with(SEALED_PREFIX)
+    }
+
+    /// Encrypt a plain secret in place with the current key
+    pub fn seal(&mut self) -> Result<(), SecretError> {
+        if !self.is_sealed() {
+            self.0 = seal_with(&key()?, &self.0)?;
+        }
+        Ok(())
+    }
+
+    /// Decrypt the secret with the current key, a plain one is returned as is
+    pub fn reveal(&self) -> Result<String, SecretError> {
+        if self.is_sealed() {
+            open_with(&key()?, &self.0)
+        } else {
+            Ok(self.0.clone())
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PassphraseFile {
+    salt: String,
+    check: String,
+}
+
+fn current() -> &'static RwLock<Option<SecretKey>> {
+    static KEY: OnceLock<RwLock<Option<SecretKey>>> = OnceLock::new();
+    KEY.get_or_init(|| RwLock::new(None))
+}
+
+fn set_current(key: SecretKey) {
+    *current().write().unwrap_or_else(PoisonError::into_inner) = Some(key);
+}
+
+/// The key in use, read from the key file or created if no master passphrase is set
+fn key() -> Result<SecretKey, SecretError> {
+    if let Some(key) = *current().read().unwrap_or_else(PoisonError::into_inner) {
+        return Ok(key);
+    }
+    let data_dir = get_data_dir()?;
+    if data_dir.join(PASSPHRASE_FILE).exists() {
+        return Err(SecretError::Locked);
+    }
+    let key = load_or_create_key_file(&data_dir.join(KEY_FILE))?;
+    set_current(key);
+    Ok(key)
+}
+
+fn load_or_create_key_file(path: &Path) -> Result<SecretKey, SecretError> {
+    if path.exists() {
+        let raw = BASE64
+            .decode(fs::read_to_string(path)?.trim())
+            .map_err(|err| SecretError::InvalidKey(err.to_string()))?;
+        return SecretKey::try_from(raw.as_slice())
+            .map_err(|_| SecretError::InvalidKey(format!("expected 32 bytes, got {}", raw.len())));
+    }
+
+    let key: SecretKey = ChaCha20Poly1305::generate_key(&mut OsRng).into();
+    write_replacing(path, &BASE64.encode(key))?;
+    info!("Created secret key file: {}", path.display());
+    Ok(key)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<SecretKey, SecretError> {
+    let mut key = SecretKey::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| SecretError::InvalidKey(err.to_string()))?;
+    Ok(key)
+}
+
+fn seal_with(key: &SecretKey, plain: &str) -> Result<String, SecretError> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let mut sealed = nonce.to_vec();
+    sealed.extend(
+        cipher
+            .encrypt(&nonce, plain.as_bytes())
+            .map_err(|_| SecretError::CryptoError)?,
+    );
+    Ok(format!("{SEALED_PREFIX}{}", BASE64.encode(sealed)))
+}
+
+fn open_with(key: &SecretKey, sealed: &str) -> Result<String, SecretError> {
+    let raw = BASE64
+        .decode(sealed.strip_prefix(SEALED_PREFIX).unwrap_or(sealed))
+        .map_err(|_| SecretError::CryptoError)?;
+    if raw.len() < NONCE_LEN {
+        return Err(SecretError::CryptoError);
+    }
+    let (nonce, ciphertext) = raw.split_at(NONCE_LEN);
+    let plain = ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| SecretError::CryptoError)?;
+    String::from_utf8(plain).map_err(|_| SecretError::CryptoError)
+}
+
+/// Whether a master passphrase is set and has not been entered yet
+pub fn secret_is_locked() -> Result<bool, SecretError> {
+    Ok(current()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .is_none()
+        && get_data_dir()?.join(PASSPHRASE_FILE).exists())
+}
+
+/// Derive the key from the master passphrase, needed before using secrets when one is set
+pub fn secret_unlock(passphrase: &str) -> Result<(), SecretError> {
+    let path = get_data_dir()?.join(PASSPHRASE_FILE);
+    if !path.exists() {
+        return Err(SecretError::NoPassphrase);
+    }
+    let file = serde_json::from_str::<PassphraseFile>(&fs::read_to_string(path)?)?;
+    let salt = BASE64
+        .decode(&file.salt)
+        .map_err(|err| SecretError::InvalidKey(err.to_string()))?;
+    let key = derive_key(passphrase, &salt)?;
+    if open_with(&key, &file.check).ok().as_deref() != Some(PASSPHRASE_CHECK) {
+        warn!("Failed to unlock secrets, wrong master passphrase");
+        return Err(SecretError::WrongPassphrase);
+    }
+    set_current(key);
+    info!("Secrets unlocked");
+    Ok(())
+}
+
+/// A key derived from a new master passphrase, or a new random key if [None], not in use yet.
+///
+/// Secrets sealed with the previous key have to be revealed before and sealed again with
+/// [SecretRotation::seal], the key is only written and switched to by [SecretRotation::apply],
+/// see [lib_set_passphrase](crate::data::library::lib_set_passphrase)
+pub fn secret_rotate(passphrase: Option<&str>) -> Result<SecretRotation, SecretError> {
+    // A salt for the passphrase, or the key itself
+    let random: SecretKey = ChaCha20Poly1305::generate_key(&mut OsRng).into();
+    Ok(match passphrase {
+        Some(passphrase) => {
+            let key = derive_key(passphrase, &random)?;
+            SecretRotation {
+                file: Some(PassphraseFile {
+                    salt: BASE64.encode(random),
+                    check: seal_with(&key, PASSPHRASE_CHECK)?,
+                }),
+                key,
+            }
+        }
+        None => SecretRotation {
+            key: random,
+            file: None,
+        },
+    })
+}
+
+/// The pending key of [secret_rotate]
+pub struct SecretRotation {
+    key: SecretKey,
+    /// Written for a master passphrase, the key file is written otherwise
+    file: Option<PassphraseFile>,
+}
+
+impl SecretRotation {
+    /// Encrypt a plain secret in place with the pending key
+    pub fn seal(&self, secret: &mut Secret) -> Result<(), SecretError> {
+        if !secret.is_sealed() {
+            secret.0 = seal_with(&self.key, &secret.0)?;
+        }
+        Ok(())
+    }
+
+    /// The file the pending key is written to, and the one of the previous kind it replaces
+    fn paths(&self) -> Result<(PathBuf, PathBuf), SecretError> {
+        let data_dir = get_data_dir()?;
+        let (written, replaced) = match self.file {
+            Some(_) => (PASSPHRASE_FILE, KEY_FILE),
+            None => (KEY_FILE, PASSPHRASE_FILE),
+        };
+        Ok((data_dir.join(written), data_dir.join(replaced)))
+    }
+
+    /// Write the pending key next to the one in use, before anything is sealed with it is stored.
+    /// An interrupted change leaves it there, see [secret_pending]
+    pub fn stage(&self) -> Result<(), SecretError> {
+        let (written, _) = self.paths()?;
+        let content = match &self.file {
+            Some(file) => serde_json::to_string_pretty(file)?,
+            None => BASE64.encode(self.key),
+        };
+        write_replacing(&pending_path(&written), &content)
+    }
+
+    /// Put the staged key in place of the previous one and switch to it
+    pub fn apply(self) -> Result<(), SecretError> {
+        let (written, replaced) = self.paths()?;
+        fs::rename(pending_path(&written), &written)?;
+        if replaced.exists() {
+            fs::remove_file(&replaced)?;
+            if self.file.is_none() {
+                info!("Master passphrase removed");
+            }
+        }
+        if self.file.is_some() {
+            info!("Master passphrase set");
+        }
+        set_current(self.key);
+        Ok(())
+    }
+
+    /// Remove the staged key after storing the secrets failed, the previous one stays in use
+    pub fn discard(self) {
+        let Ok((written, _)) = self.paths() else {
+            return;
+        };
+        let pending = pending_path(&written);
+        if pending.exists()
+            && let Err(err) = fs::remove_file(&pending)
+        {
+            warn!("Failed to remove staged key {}: {}", pending.display(), err);
+        }
+    }
+}
+
+fn pending_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".new");
+    path.with_file_name(name)
+}
+
+/// A key staged by a passphrase change that has not been put in place, which holds the key
+/// of the stored secrets if the change was interrupted after storing them
+pub fn secret_pending() -> Result<Option<PathBuf>, SecretError> {
+    let data_dir = get_data_dir()?;
+    Ok([PASSPHRASE_FILE, KEY_FILE]
+        .into_iter()
+        .map(|name| pending_path(&data_dir.join(name)))
+        .find(|path| path.exists()))
+}
+
+/// Write through a temporary file, the previous content stays until it is replaced as a whole
+fn write_replacing(path: &Path, content: &str) -> Result<(), SecretError> {
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, content)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&temp, fs::Permissions::from_mode(0o600))?;
+    }
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_seal() {
+        let key = derive_key("passphrase", b"some salt").unwrap();
+        let sealed = seal_with(&key, "p@ss").unwrap();
+        assert!(sealed.starts_with(SEALED_PREFIX));
+        assert_ne!(sealed, seal_with(&key, "p@ss").unwrap());
+        assert_eq!(open_with(&key, &sealed).unwrap(), "p@ss");
+
+        let mut secret = Secret::new("p@ss");
+        let rotation = secret_rotate(Some("passphrase")).unwrap();
+        rotation.seal(&mut secret).unwrap();
+        assert!(secret.is_sealed());
+        assert_eq!(open_with(&rotation.key, &secret.0).unwrap(), "p@ss");
+        assert_eq!(secret_rotate(None).unwrap().key.len(), 32);
+
+        let other = derive_key("other", b"some salt").unwrap();
+        assert!(open_with(&other, &sealed).is_err());
+        assert_eq!(format!("{:?}", Secret::new("p@ss")), "Secret(***)");
+    }
+}
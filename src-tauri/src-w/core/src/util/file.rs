@@ -1,6 +1,7 @@
 use std::env::current_dir;
 use std::path::{Path, PathBuf};
 use std::{fs, io};
+use tracing::warn;
 
 /// Get the current working directory, appending a subdirectory in test, debug
 pub fn cd() -> PathBuf {
@@ -35,6 +36,17 @@ pub fn cd_test() -> PathBuf {
     cd_with(TEST_DIR)
 }
 
+/// Get a fresh, empty subdirectory of the test directory, so tests running in parallel don't collide
+#[cfg(test)]
+pub fn cd_test_dir(name: &str) -> PathBuf {
+    let dir = cd_test().join(name);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
 /// Clear the test directory
 #[cfg(test)]
 pub fn cd_test_clear() {
@@ -46,23 +58,121 @@ pub fn cd_test_clear() {
     }
 }
 
-pub fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<(), io::Error> {
-    if !dst.as_ref().exists() {
-        fs::create_dir_all(&dst)?;
+/// Recreate the directory tree of `src` in `dst`, placing each file with the given function
+fn mirror_dir_all(
+    src: &Path,
+    dst: &Path,
+    place: &dyn Fn(&Path, &Path) -> Result<(), io::Error>,
+) -> Result<(), io::Error> {
+    if !dst.exists() {
+        fs::create_dir_all(dst)?;
     }
 
     for entry in fs::read_dir(src)? {
         let entry = entry?;
         let ty = entry.file_type()?;
         let src_path = entry.path();
-        let dst_path = dst.as_ref().join(entry.file_name());
+        let dst_path = dst.join(entry.file_name());
 
         if ty.is_dir() {
-            copy_dir_all(&src_path, &dst_path)?;
+            mirror_dir_all(&src_path, &dst_path, place)?;
         } else {
-            fs::copy(&src_path, &dst_path)?;
+            place(&src_path, &dst_path)?;
         }
     }
 
     Ok(())
 }
+
+pub fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<(), io::Error> {
+    mirror_dir_all(src.as_ref(), dst.as_ref(), &|src, dst| {
+        fs::copy(src, dst).map(|_| ())
+    })
+}
+
+/// Mirror a directory with hard links to its files, both must be on the same filesystem
+pub fn hard_link_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<(), io::Error> {
+    mirror_dir_all(src.as_ref(), dst.as_ref(), &|src, dst| {
+        fs::hard_link(src, dst)
+    })
+}
+
+/// Mirror a directory with copy-on-write clones of its files, fails if the filesystem does not support it
+pub fn reflink_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<(), io::Error> {
+    mirror_dir_all(src.as_ref(), dst.as_ref(), &|src, dst| {
+        reflink_copy::reflink(src, dst)
+    })
+}
+
+/// Create a symbolic link at `link` pointing to the directory `target`
+pub fn symlink_dir(target: impl AsRef<Path>, link: impl AsRef<Path>) -> Result<(), io::Error> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, link)
+    }
+
+    #[cfg(windows)]
+    {
+        std::os::windows::fs::symlink_dir(target, link)
+    }
+}
+
+/// Create a symbolic link at `link` pointing to `target` as it is, relative to the link's directory
+/// unless absolute. Windows tells a directory link from a file link by the target
+pub fn symlink(target: impl AsRef<Path>, link: impl AsRef<Path>) -> Result<(), io::Error> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, link)
+    }
+
+    #[cfg(windows)]
+    {
+        let resolved = link
+            .as_ref()
+            .parent()
+            .map_or_else(|| target.as_ref().to_path_buf(), |dir| dir.join(&target));
+        if resolved.is_dir() {
+            std::os::windows::fs::symlink_dir(target, link)
+        } else {
+            std::os::windows::fs::symlink_file(target, link)
+        }
+    }
+}
+
+/// Remove a symbolic link to a directory, leaving the directory it points to alone
+pub fn remove_symlink_dir(link: impl AsRef<Path>) -> Result<(), io::Error> {
+    #[cfg(unix)]
+    {
+        fs::remove_file(link)
+    }
+
+    #[cfg(windows)]
+    {
+        fs::remove_dir(link)
+    }
+}
+
+/// Move a directory to `dst` by renaming it, or by copying it when renaming fails, e.g. across filesystems.
+/// An existing `dst` must be an empty directory, which is replaced, and left empty if copying fails
+pub fn move_dir(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<(), io::Error> {
+    if dst.as_ref().exists() {
+        fs::remove_dir(&dst)?;
+    }
+    if fs::rename(&src, &dst).is_ok() {
+        return Ok(());
+    }
+
+    if let Err(err) = copy_dir_all(&src, &dst) {
+        let _ = fs::remove_dir_all(&dst);
+        fs::create_dir_all(&dst)?;
+        return Err(err);
+    }
+    if let Err(err) = fs::remove_dir_all(&src) {
+        warn!(
+            "Failed to remove {} after copying it: {}",
+            src.as_ref().display(),
+            err
+        );
+    }
+    Ok(())
+}
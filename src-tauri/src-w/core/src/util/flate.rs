@@ -1,160 +1,915 @@
+use crate::foundation::config;
 use crate::util::create_hidden_command;
+use crate::util::volume::{self, VolumeReader, VolumeScheme, VolumeSet};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sevenz_rust2::Password;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::fs::File;
-use std::path::Path;
-use std::process::Command;
-use std::{fs, io};
-use tracing::info;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+use std::{fs, io, thread};
+use thiserror::Error;
+use tracing::{debug, info, warn};
 use unrar::error::UnrarError;
 use zip::ZipArchive;
+use zip::result::ZipError;
 
-/// Extract a zip file to a specified directory
-pub fn decompress_zip(
-    zip: impl AsRef<Path>,
-    dst: impl AsRef<Path>,
-    password: Option<&str>,
-) -> Result<(), io::Error> {
-    let file = File::open(&zip)?;
-    let archive = ZipArchive::new(file)?;
+/// Minimal interval between two progress reports, except for the first and the last one
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
 
-    if !dst.as_ref().exists() {
-        fs::create_dir_all(&dst)?;
+/// Same as the one used by [sevenz_rust2] when compressing a directory
+const MAX_BLOCK_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum FlateError {
+    #[error("IO failure: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("ZIP failure: {0}")]
+    Zip(#[from] ZipError),
+
+    #[error("7z failure: {0}")]
+    SevenZ(#[from] sevenz_rust2::Error),
+
+    #[error("RAR failure: {0}")]
+    Rar(#[from] UnrarError),
+
+    #[error("Fail in 7z command exec: {0}")]
+    External(String),
+
+    #[error("Not a supported archive format: {0}")]
+    Unsupported(String),
+
+    #[error("None of the {0} password candidates opens the archive")]
+    PasswordNotFound(usize),
+
+    #[error("Missing archive volumes: {}", .0.join(", "))]
+    MissingVolumes(Vec<String>),
+
+    #[error("Unsafe entry in archive: {0}")]
+    UnsafePath(String),
+
+    #[error("Entries of {0} can't be checked before extracting")]
+    Unchecked(String),
+
+    #[error("Operation cancelled")]
+    Cancelled,
+}
+
+/// Progress of a compression or decompression, bytes are counted uncompressed
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Default)]
+pub struct FlateProgress {
+    pub bytes_done: u64,
+    pub bytes_total: Option<u64>,
+    pub entries_done: u64,
+    pub entries_total: Option<u64>,
+    /// Name of the entry being processed, relative to the archive root
+    pub current: Option<String>,
+}
+
+/// A file inside an archive, see [list_entries]
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct ArchiveEntry {
+    /// Relative to the archive root, with `/` separators
+    pub path: String,
+    /// Uncompressed size
+    pub size: u64,
+    /// [None] where the format doesn't tell, like files sharing a solid 7z block
+    pub compressed_size: Option<u64>,
+    pub modified: Option<DateTime<Utc>>,
+    pub encrypted: bool,
+}
+
+/// Cancels a running operation when triggered, clones share the same state
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    fn out_file(mut archive: ZipArchive<File>, dst: impl AsRef<Path>) -> Result<(), io::Error> {
-        for cur in 0..archive.len() {
-            let mut file = archive.by_index(cur)?;
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
 
-            let out = dst.as_ref().join(file.mangled_name());
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
 
-            if file.name().ends_with('/') {
-                fs::create_dir_all(&out)?;
-            } else {
-                if let Some(parent) = out.parent() {
-                    if !parent.exists() {
-                        fs::create_dir_all(parent)?;
-                    }
-                }
+type ProgressCallback<'a> = Box<dyn FnMut(&FlateProgress) + 'a>;
 
-                let mut out_file = File::create(&out)?;
-                io::copy(&mut file, &mut out_file)?;
-            }
+/// Receives the progress of an operation and tells it whether to stop.
+///
+/// Reports are throttled to [REPORT_INTERVAL], the callback runs on the thread doing the work
+#[derive(Default)]
+pub struct FlateMonitor<'a> {
+    cancel: CancelToken,
+    on_progress: RefCell<Option<ProgressCallback<'a>>>,
+    progress: RefCell<FlateProgress>,
+    last_report: Cell<Option<Instant>>,
+}
+
+impl<'a> FlateMonitor<'a> {
+    pub fn new(cancel: CancelToken, on_progress: impl FnMut(&FlateProgress) + 'a) -> Self {
+        Self {
+            cancel,
+            on_progress: RefCell::new(Some(Box::new(on_progress))),
+            ..Default::default()
         }
+    }
+
+    /// A monitor without progress reports, which is never cancelled unless its token is used
+    pub fn silent() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.cancel
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// The latest progress, including changes not reported yet
+    pub fn progress(&self) -> FlateProgress {
+        self.progress.borrow().clone()
+    }
+
+    fn check(&self) -> Result<(), FlateError> {
+        if self.is_cancelled() {
+            Err(FlateError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Any failure after cancelling is caused by the cancellation itself
+    fn fail(&self, err: impl Into<FlateError>) -> FlateError {
+        if self.is_cancelled() {
+            FlateError::Cancelled
+        } else {
+            err.into()
+        }
+    }
+
+    pub(crate) fn begin(&self, bytes_total: Option<u64>, entries_total: Option<u64>) {
+        *self.progress.borrow_mut() = FlateProgress {
+            bytes_total,
+            entries_total,
+            ..Default::default()
+        };
+        self.report(true);
+    }
 
+    /// Start processing an entry, fails if cancelled
+    pub(crate) fn enter(&self, name: impl Into<String>) -> Result<(), FlateError> {
+        self.check()?;
+        self.progress.borrow_mut().current = Some(name.into());
+        self.report(false);
         Ok(())
     }
 
-    fn out_file_with_password(
-        mut archive: ZipArchive<File>,
-        dst: impl AsRef<Path>,
-        password: &str,
-    ) -> Result<(), io::Error> {
-        for cur in 0..archive.len() {
-            let mut file = archive.by_index_decrypt(cur, password.as_bytes())?;
+    fn advance(&self, bytes: u64) {
+        self.progress.borrow_mut().bytes_done += bytes;
+        self.report(false);
+    }
 
-            let out = dst.as_ref().join(file.mangled_name());
+    pub(crate) fn leave(&self) {
+        self.progress.borrow_mut().entries_done += 1;
+        self.report(false);
+    }
 
-            if file.name().ends_with('/') {
-                fs::create_dir_all(&out)?;
-            } else {
-                if let Some(parent) = out.parent() {
-                    if !parent.exists() {
-                        fs::create_dir_all(parent)?;
-                    }
-                }
+    fn update(&self, f: impl FnOnce(&mut FlateProgress)) {
+        f(&mut self.progress.borrow_mut());
+        self.report(false);
+    }
+
+    pub(crate) fn finish(&self) {
+        self.progress.borrow_mut().current = None;
+        self.report(true);
+    }
+
+    fn report(&self, force: bool) {
+        let now = Instant::now();
+        if !force
+            && self
+                .last_report
+                .get()
+                .is_some_and(|last| now.duration_since(last) < REPORT_INTERVAL)
+        {
+            return;
+        }
+        self.last_report.set(Some(now));
+        if let Some(on_progress) = self.on_progress.borrow_mut().as_mut() {
+            on_progress(&self.progress.borrow());
+        }
+    }
+}
 
-                let mut out_file = File::create(&out)?;
-                io::copy(&mut file, &mut out_file)?;
+/// Counts the bytes read into the monitor, fails once cancelled
+struct MonitoredRead<'m, 'a, R> {
+    inner: R,
+    monitor: &'m FlateMonitor<'a>,
+}
+
+impl<R: Read> Read for MonitoredRead<'_, '_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.monitor.is_cancelled() {
+            return Err(io::Error::other(FlateError::Cancelled));
+        }
+        let read = self.inner.read(buf)?;
+        self.monitor.advance(read as u64);
+        Ok(read)
+    }
+}
+
+/// A file opened on the first read, so a solid block does not hold all its files open
+struct MonitoredFile<'m, 'a> {
+    path: PathBuf,
+    name: String,
+    file: Option<File>,
+    done: bool,
+    monitor: &'m FlateMonitor<'a>,
+}
+
+impl Read for MonitoredFile<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => {
+                self.monitor.enter(&self.name).map_err(io::Error::other)?;
+                self.file.insert(File::open(&self.path)?)
             }
+        };
+        let read = MonitoredRead {
+            inner: file,
+            monitor: self.monitor,
+        }
+        .read(buf)?;
+        if read == 0 {
+            self.done = true;
+            self.file = None;
+            self.monitor.leave();
         }
+        Ok(read)
+    }
+}
 
-        Ok(())
+/// The volumes of an archive file, failing if any of them is missing
+fn volumes(archive: &Path) -> Result<VolumeSet, FlateError> {
+    let set = VolumeSet::find(archive)?;
+    if !set.missing.is_empty() {
+        warn!(
+            "Missing volumes of {}: {:?}",
+            archive.display(),
+            set.missing
+        );
+        return Err(FlateError::MissingVolumes(
+            set.missing
+                .iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect(),
+        ));
+    }
+    Ok(set)
+}
+
+/// Components of an entry name, split on both `/` and `\` as either may come from Windows
+fn name_components(name: &str) -> impl Iterator<Item = &str> {
+    name.split(['/', '\\'])
+        .filter(|part| !part.is_empty() && *part != ".")
+}
+
+/// Whether the name is rooted, like `/etc`, `\\server` or `C:\Windows`
+fn is_rooted(name: &str) -> bool {
+    let drive = name.as_bytes();
+    name.starts_with(['/', '\\'])
+        || (drive.len() >= 2 && drive[0].is_ascii_alphabetic() && drive[1] == b':')
+}
+
+/// Where an entry is extracted to under the destination, every extractor goes through this.
+///
+/// Rejects with [FlateError::UnsafePath] names escaping the destination by `..` or a root,
+/// and ones written through a symlink extracted before
+pub(crate) fn entry_path(dst: &Path, name: &str) -> Result<PathBuf, FlateError> {
+    let unsafe_path = || {
+        warn!("Rejected unsafe entry in archive: {name}");
+        FlateError::UnsafePath(name.to_string())
+    };
+    if is_rooted(name) {
+        return Err(unsafe_path());
+    }
+    let mut path = dst.to_path_buf();
+    for part in name_components(name) {
+        if part == ".." {
+            return Err(unsafe_path());
+        }
+        if path != dst && path.symlink_metadata().is_ok_and(|meta| meta.is_symlink()) {
+            return Err(unsafe_path());
+        }
+        path.push(part);
+    }
+    Ok(path)
+}
+
+/// Check the target of a symlink entry stays inside the destination.
+///
+/// Only a leading run of `..` is allowed and it can't climb above the destination,
+/// so following other symlinks inside can't lead outside either
+pub(crate) fn check_link(name: &str, target: &str) -> Result<(), FlateError> {
+    let unsafe_link = || {
+        warn!("Rejected unsafe symlink in archive: {name} -> {target}");
+        FlateError::UnsafePath(format!("{name} -> {target}"))
+    };
+    if target.is_empty() || is_rooted(target) {
+        return Err(unsafe_link());
+    }
+    let depth = name_components(name).count().saturating_sub(1);
+    let mut climbs = 0;
+    let mut descended = false;
+    for part in name_components(target) {
+        match part {
+            ".." if descended => return Err(unsafe_link()),
+            ".." => climbs += 1,
+            _ => descended = true,
+        }
+    }
+    if climbs > depth {
+        return Err(unsafe_link());
+    }
+    Ok(())
+}
+
+/// Check the symlinks left by an extractor that creates them on its own, like the external 7z.
+/// An unsafe one is removed before failing
+fn check_extracted_links(dst: &Path) -> Result<(), FlateError> {
+    for entry in walkdir::WalkDir::new(dst) {
+        let entry = entry.map_err(io::Error::from)?;
+        if !entry.path_is_symlink() {
+            continue;
+        }
+        let name = entry
+            .path()
+            .strip_prefix(dst)
+            .unwrap_or(entry.path())
+            .to_string_lossy()
+            .to_string();
+        let target = fs::read_link(entry.path())?;
+        if let Err(err) = check_link(&name, &target.to_string_lossy()) {
+            fs::remove_file(entry.path())?;
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Check the entries an external 7z is about to extract, by their names and whether each is a
+/// symlink. Besides what [entry_path] rejects, nothing may be written under a symlink entry,
+/// as 7z would follow it. The targets are only known once extracted, see [check_extracted_links]
+fn check_external_entries(dst: &Path, entries: &[(&str, bool)]) -> Result<(), FlateError> {
+    let links = entries
+        .iter()
+        .filter(|(_, is_link)| *is_link)
+        .map(|(name, _)| name_components(name).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    for (name, is_link) in entries {
+        entry_path(dst, name)?;
+        let parts = name_components(name).collect::<Vec<_>>();
+        let through_link = links
+            .iter()
+            .any(|link| parts.starts_with(link) && (parts.len() > link.len() || !is_link));
+        if through_link {
+            warn!("Rejected entry under a symlink in archive: {name}");
+            return Err(FlateError::UnsafePath(name.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a 7z entry is a symlink, by the unix mode p7zip and 7-Zip keep in the high half
+/// of the attributes, or the reparse point attribute of Windows
+fn is_7z_symlink(entry: &sevenz_rust2::SevenZArchiveEntry) -> bool {
+    const REPARSE_POINT: u32 = 0x400;
+    const UNIX_EXTENSION: u32 = 0x8000;
+    const S_IFMT: u32 = 0o170000;
+    const S_IFLNK: u32 = 0o120000;
+    let attributes = entry.windows_attributes;
+    entry.has_windows_attributes
+        && (attributes & REPARSE_POINT != 0
+            || (attributes & UNIX_EXTENSION != 0 && (attributes >> 16) & S_IFMT == S_IFLNK))
+}
+
+/// Entries of an archive as listed by the external 7z, with whether each is a symlink.
+/// For archives no header can be read of in process, like spanned zip volumes
+fn list_7z_external(
+    external: &External7z,
+    archive: &Path,
+) -> Result<Vec<(String, bool)>, FlateError> {
+    let output = external
+        .command()
+        .arg("l")
+        .arg("-slt")
+        .arg("-sccUTF-8")
+        .arg(archive)
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(FlateError::External(
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+    Ok(parse_7z_listing(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse the technical listing of `7z l -slt`, where the entries follow a `----------` line
+/// as `Key = Value` blocks. A symlink has a unix mode like `lrwxrwxrwx` in its attributes
+fn parse_7z_listing(listing: &str) -> Vec<(String, bool)> {
+    let mut entries: Vec<(String, bool)> = Vec::new();
+    for line in listing
+        .lines()
+        .skip_while(|line| line.trim() != "----------")
+    {
+        if let Some(path) = line.strip_prefix("Path = ") {
+            entries.push((path.to_string(), false));
+        } else if let Some(attributes) = line.strip_prefix("Attributes = ")
+            && let Some((_, is_link)) = entries.last_mut()
+        {
+            *is_link = attributes
+                .split_whitespace()
+                .any(|mode| mode.len() == 10 && mode.starts_with('l'));
+        }
+    }
+    entries
+}
+
+/// Offsets of a spanned zip start over in each volume, which the zip crate can't follow
+fn spanned_zip(set: &VolumeSet) -> FlateError {
+    FlateError::Unsupported(format!(
+        "spanned zip volumes of {} without the 7z command",
+        set.entry().display()
+    ))
+}
+
+/// Open a zip archive, reading the volumes of a byte split one as a whole
+fn open_zip(zip: &Path) -> Result<ZipArchive<VolumeReader>, FlateError> {
+    let set = volumes(zip)?;
+    if set.scheme == Some(VolumeScheme::ZipSpanned) {
+        return Err(spanned_zip(&set));
+    }
+    Ok(ZipArchive::new(set.open()?)?)
+}
+
+/// Extract a zip file to a specified directory, spanned volumes are left to the external 7z
+pub fn decompress_zip(
+    zip: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    password: Option<&str>,
+    monitor: &FlateMonitor,
+) -> Result<(), FlateError> {
+    let set = volumes(zip.as_ref())?;
+    if set.scheme == Some(VolumeScheme::ZipSpanned) {
+        let Some(external) = select_7z(password)? else {
+            return Err(spanned_zip(&set));
+        };
+        // Listed up front, as the zip crate can't read the central directory of the volumes
+        let entries = list_7z_external(&external, set.entry())?;
+        let entries = entries
+            .iter()
+            .map(|(name, is_link)| (name.as_str(), *is_link))
+            .collect::<Vec<_>>();
+        check_external_entries(dst.as_ref(), &entries)?;
+        monitor.begin(None, None);
+        return decompress_7z_external(&external, set.entry(), dst.as_ref(), password, monitor);
+    }
+    let mut archive = ZipArchive::new(set.open()?)?;
+
+    if !dst.as_ref().exists() {
+        fs::create_dir_all(&dst)?;
+    }
+
+    let mut bytes_total = 0;
+    for cur in 0..archive.len() {
+        bytes_total += archive.by_index_raw(cur)?.size();
+    }
+    monitor.begin(Some(bytes_total), Some(archive.len() as u64));
+
+    for cur in 0..archive.len() {
+        let mut file = match password {
+            Some(pwd) => archive.by_index_decrypt(cur, pwd.as_bytes())?,
+            None => archive.by_index(cur)?,
+        };
+
+        let out = entry_path(dst.as_ref(), file.name())?;
+        monitor.enter(file.name())?;
+
+        if file.is_symlink() {
+            // Written as a plain file holding the target, like before symlinks were checked
+            let mut target = String::new();
+            file.read_to_string(&mut target)?;
+            check_link(file.name(), &target)?;
+            if let Some(parent) = out.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            monitor.advance(target.len() as u64);
+            fs::write(&out, target)?;
+        } else if file.name().ends_with('/') {
+            fs::create_dir_all(&out)?;
+        } else {
+            if let Some(parent) = out.parent()
+                && !parent.exists()
+            {
+                fs::create_dir_all(parent)?;
+            }
+
+            let mut out_file = File::create(&out)?;
+            let mut reader = MonitoredRead {
+                inner: &mut file,
+                monitor,
+            };
+            io::copy(&mut reader, &mut out_file).map_err(|e| monitor.fail(e))?;
+        }
+
+        monitor.leave();
     }
 
+    monitor.finish();
+    Ok(())
+}
+
+fn rar_archive<'a>(rar: &'a Path, password: Option<&'a str>) -> unrar::Archive<'a> {
     match password {
-        Some(pwd) => out_file_with_password(archive, dst, pwd),
-        None => out_file(archive, dst),
+        Some(pwd) => unrar::Archive::with_password(rar, pwd.as_bytes()),
+        None => unrar::Archive::new(rar),
     }
 }
 
+/// Extract a rar file, unrar follows the volumes of a split one from the first
 pub fn decompress_rar(
     rar: impl AsRef<Path>,
     dst: impl AsRef<Path>,
     password: Option<&str>,
-) -> Result<(), UnrarError> {
-    let mut archive = match password {
-        Some(pwd) => unrar::Archive::with_password(&rar, pwd.as_bytes()).open_for_processing()?,
-        None => unrar::Archive::new(&rar).open_for_processing()?,
-    };
+    monitor: &FlateMonitor,
+) -> Result<(), FlateError> {
+    let set = volumes(rar.as_ref())?;
+    let (mut bytes_total, mut entries_total) = (0, 0);
+    for header in rar_archive(set.entry(), password).open_for_listing()? {
+        let header = header?;
+        if header.is_file() {
+            bytes_total += header.unpacked_size;
+            entries_total += 1;
+        }
+    }
+    monitor.begin(Some(bytes_total), Some(entries_total));
 
+    let mut archive = rar_archive(set.entry(), password).open_for_processing()?;
     while let Some(header) = archive.read_header()? {
         archive = if header.entry().is_file() {
-            header.extract_with_base(&dst)?
+            let size = header.entry().unpacked_size;
+            let name = header.entry().filename.to_string_lossy().to_string();
+            // unrar checks the targets of the symlinks it creates by itself
+            entry_path(dst.as_ref(), &name)?;
+            monitor.enter(name)?;
+            let archive = header.extract_with_base(&dst)?;
+            monitor.advance(size);
+            monitor.leave();
+            archive
         } else {
             header.skip()?
         }
     }
 
+    monitor.finish();
     Ok(())
 }
 
-/// Check if 7z exe is in the system path
-fn is_7z_in_path() -> bool {
-    match Command::new("7z").arg("--help").output() {
-        Ok(out) => out.status.success(),
-        Err(_) => false,
+/// Which implementation handles 7z archives, and spanned zips that only 7z can read
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Default)]
+pub enum SevenZBackend {
+    /// The external 7z when there is one and the password can be piped, else the library
+    #[default]
+    Auto,
+    /// Always the external 7z, failing if it is missing or the password can't be piped
+    External,
+    /// Always [sevenz_rust2]
+    Internal,
+}
+
+/// The implementation that wrote an archive, see [compress]
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub enum ArchiveBackend {
+    /// The external 7z, with the first line of its banner
+    External7z(String),
+    /// [sevenz_rust2]
+    Internal7z,
+    Zip,
+    /// Along with the gzip, zstd or xz encoder
+    Tar,
+}
+
+/// A 7z executable found to work, see [pick_7z]
+#[derive(Debug, Clone)]
+struct External7z {
+    program: PathBuf,
+    banner: String,
+}
+
+impl External7z {
+    fn command(&self) -> Command {
+        create_hidden_command(&self.program)
+    }
+
+    fn backend(&self) -> ArchiveBackend {
+        ArchiveBackend::External7z(self.banner.lines().next().unwrap_or_default().to_string())
+    }
+}
+
+/// The banner the 7z exe prints before its usage, [None] if it can't be run.
+///
+/// Cached per program, so `7z --help` runs once instead of on every call
+fn external_7z_banner(program: &Path) -> Option<String> {
+    static BANNERS: OnceLock<Mutex<HashMap<PathBuf, Option<String>>>> = OnceLock::new();
+    let mut banners = BANNERS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    banners
+        .entry(program.to_path_buf())
+        .or_insert_with(|| {
+            let banner = match create_hidden_command(program).arg("--help").output() {
+                Ok(out) if out.status.success() => Some(
+                    String::from_utf8_lossy(&out.stdout)
+                        .lines()
+                        .map(str::trim)
+                        .take_while(|line| !line.starts_with("Usage"))
+                        .filter(|line| !line.is_empty())
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                ),
+                _ => None,
+            };
+            match &banner {
+                Some(banner) => info!("Found 7z at {}: {banner}", program.display()),
+                None => info!("No working 7z at {}", program.display()),
+            }
+            banner
+        })
+        .clone()
+}
+
+/// Whether the external 7z can be handed the password through stdin instead of the command line,
+/// where any other process could read it.
+///
+/// p7zip asks the terminal rather than stdin, and 7z decodes stdin with the console code page,
+/// so anything beyond printable ASCII may arrive mangled
+fn can_pipe_7z_password(banner: &str, password: &str) -> bool {
+    !banner.contains("p7zip")
+        && !password.is_empty()
+        && password.chars().all(|c| c.is_ascii_graphic() || c == ' ')
+}
+
+/// The external 7z to use, or [None] for [sevenz_rust2], as the backend and password allow
+fn pick_7z(
+    backend: SevenZBackend,
+    program: &Path,
+    password: Option<&str>,
+) -> Result<Option<External7z>, FlateError> {
+    if backend == SevenZBackend::Internal {
+        return Ok(None);
+    }
+    let Some(banner) = external_7z_banner(program) else {
+        return match backend {
+            SevenZBackend::External => Err(FlateError::External(format!(
+                "No working 7z at {}",
+                program.display()
+            ))),
+            _ => Ok(None),
+        };
+    };
+    if password.is_some_and(|pwd| !can_pipe_7z_password(&banner, pwd)) {
+        return match backend {
+            SevenZBackend::External => Err(FlateError::External(format!(
+                "The password can't be piped to {}",
+                program.display()
+            ))),
+            _ => Ok(None),
+        };
+    }
+    Ok(Some(External7z {
+        program: program.to_path_buf(),
+        banner,
+    }))
+}
+
+/// [pick_7z] with the backend and path of the config
+fn select_7z(password: Option<&str>) -> Result<Option<External7z>, FlateError> {
+    let (backend, program) = match config::get() {
+        Ok(config) => (config.sevenz_backend(), config.sevenz_program()),
+        Err(err) => {
+            warn!("Failed to read the 7z backend from config: {err}");
+            (SevenZBackend::default(), PathBuf::from("7z"))
+        }
+    };
+    pick_7z(backend, &program, password)
+}
+
+/// Files of a directory to compress, with their names in the archive and sizes
+fn collect_files(input_dir: &Path) -> Result<Vec<(PathBuf, String, u64)>, io::Error> {
+    let mut files = Vec::new();
+    for entry in walkdir::WalkDir::new(input_dir).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let name = entry
+            .path()
+            .strip_prefix(input_dir)
+            .unwrap_or(entry.path())
+            .to_string_lossy()
+            .to_string();
+        let size = entry.metadata()?.len();
+        files.push((entry.into_path(), name, size));
+    }
+    Ok(files)
+}
+
+/// Parse a progress line printed by 7z with `-bsp1`, like ` 42% 12 + dir/file.bin`,
+/// into the percentage, the amount of finished files and the current file
+fn parse_7z_progress(line: &str) -> Option<(u64, Option<u64>, Option<String>)> {
+    let (percent, rest) = line.trim().split_once('%')?;
+    let percent = percent.trim().parse::<u64>().ok()?;
+    let rest = rest.trim_start();
+    let (count, rest) = match rest.split_once(' ') {
+        Some((count, rest)) if count.parse::<u64>().is_ok() => (count.parse().ok(), rest),
+        _ => (None, rest),
+    };
+    let current = rest
+        .strip_prefix(['+', '-', 'U'])
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+    Some((percent, count, current))
+}
+
+/// Run an external 7z command to its end, turning its percentage into progress reports,
+/// the process is killed once cancelled.
+///
+/// The input, like the answers to a password prompt, is written to its stdin
+fn run_7z_external(
+    mut command: Command,
+    input: Option<String>,
+    monitor: &FlateMonitor,
+) -> Result<(), FlateError> {
+    command
+        .arg("-bsp1")
+        .arg("-bso0")
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = command.spawn()?;
+    if let Some(input) = input {
+        // Dropped right after, so 7z sees the end of input instead of waiting for more
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        if let Err(err) = stdin.write_all(input.as_bytes()) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(err.into());
+        }
+    }
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        // The progress line is redrawn with backspaces, not newlines
+        let mut buf = [0u8; 1024];
+        let mut line = Vec::new();
+        while let Ok(read) = stdout.read(&mut buf)
+            && read > 0
+        {
+            for &byte in &buf[..read] {
+                if matches!(byte, b'\x08' | b'\r' | b'\n') {
+                    if let Some(progress) = parse_7z_progress(&String::from_utf8_lossy(&line))
+                        && tx.send(progress).is_err()
+                    {
+                        return;
+                    }
+                    line.clear();
+                } else {
+                    line.push(byte);
+                }
+            }
+        }
+    });
+    let stderr = thread::spawn(move || {
+        let mut output = String::new();
+        let _ = stderr.read_to_string(&mut output);
+        output
+    });
+
+    let status = loop {
+        if monitor.is_cancelled() {
+            warn!("Killing 7z process {} on cancellation", child.id());
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(FlateError::Cancelled);
+        }
+        match rx.recv_timeout(REPORT_INTERVAL) {
+            Ok((percent, count, current)) => monitor.update(|progress| {
+                if let Some(total) = progress.bytes_total {
+                    progress.bytes_done = total * percent.min(100) / 100;
+                }
+                if let Some(count) = count {
+                    progress.entries_done = count;
+                }
+                if current.is_some() {
+                    progress.current = current;
+                }
+            }),
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => break child.wait()?,
+        }
+    };
+
+    if !status.success() {
+        return Err(FlateError::External(stderr.join().unwrap_or_default()));
     }
+
+    let progress = monitor.progress();
+    monitor.update(|p| {
+        p.bytes_done = progress.bytes_total.unwrap_or(progress.bytes_done);
+        p.entries_done = progress.entries_total.unwrap_or(progress.entries_done);
+    });
+    monitor.finish();
+    Ok(())
 }
 
-/// Compress a directory to a 7z file, optionally with a password and compression level
+/// Compress a directory to a 7z file, see [ArchiveOptions] for the level, solid mode and encryption.
+/// Returns the backend picked by the config, see [SevenZBackend]
 pub fn compress_7z(
     input_dir: impl AsRef<Path>,
     output_file_path: impl AsRef<Path>,
-    password: Option<&str>,
-    compression_level: Option<u32>,
-) -> Result<(), String> {
-    if is_7z_in_path() {
+    options: &ArchiveOptions,
+    monitor: &FlateMonitor,
+) -> Result<ArchiveBackend, FlateError> {
+    let files = collect_files(input_dir.as_ref())?;
+    monitor.begin(
+        Some(files.iter().map(|(_, _, size)| size).sum()),
+        Some(files.len() as u64),
+    );
+    let password = options.password.as_deref();
+
+    if let Some(external) = select_7z(password)? {
         info!(
-            "Using external 7z command in compressing {}",
+            "Using external 7z command {} in compressing {}",
+            external.program.display(),
             input_dir.as_ref().display()
         );
 
-        fn compress_7z_dir_external(
-            input_dir: impl AsRef<Path>,
-            output_file_path: impl AsRef<Path>,
-            password: Option<&str>,
-            compression_level: Option<u32>,
-        ) -> Result<(), io::Error> {
-            let mut command = create_hidden_command("7z");
-
-            command.arg("a");
+        let mut command = external.command();
 
-            let level = compression_level.unwrap_or(5);
-            command.arg(format!("-mx={level}"));
+        command.arg("a");
 
-            if let Some(pwd) = password {
-                command.arg(format!("-p{pwd}"));
-            }
+        command.arg(format!("-mx={}", options.level()));
+        if !options.solid {
+            command.arg("-ms=off");
+        }
 
-            command.arg(output_file_path.as_ref());
-            command.current_dir(input_dir.as_ref());
-            command.arg("*");
-
-            let output = command.output()?;
-            if !output.status.success() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "Fail in 7z command exec: {}",
-                        String::from_utf8_lossy(&output.stderr)
-                    ),
-                ));
+        // A bare `-p` makes 7z prompt for the password and then ask to verify it
+        if password.is_some() {
+            command.arg("-p");
+            if options.encrypt_header {
+                command.arg("-mhe=on");
             }
-
-            Ok(())
         }
 
-        compress_7z_dir_external(input_dir, output_file_path, password, compression_level)
-            .map_err(|e| e.to_string())
+        command.arg(output_file_path.as_ref());
+        command.current_dir(input_dir.as_ref());
+        command.arg("*");
+
+        let input = password.map(|pwd| format!("{pwd}\n{pwd}\n"));
+        run_7z_external(command, input, monitor)?;
+        Ok(external.backend())
     } else {
         info!(
             "Using internal 7z library in compressing {}",
@@ -162,106 +917,934 @@ pub fn compress_7z(
         );
 
         fn compress_7z_dir_internal(
-            input_dir: impl AsRef<Path>,
+            files: Vec<(PathBuf, String, u64)>,
             output_file_path: impl AsRef<Path>,
-            password: Option<&str>,
-            compression_level: Option<u32>,
+            options: &ArchiveOptions,
+            monitor: &FlateMonitor,
         ) -> Result<(), sevenz_rust2::Error> {
             use sevenz_rust2::lzma::LZMA2Options;
-            use sevenz_rust2::{AesEncoderOptions, SevenZWriter};
+            use sevenz_rust2::{
+                AesEncoderOptions, EncoderConfiguration, EncoderMethod, SeqReader,
+                SevenZArchiveEntry, SevenZWriter, SourceReader,
+            };
 
             let mut writer = SevenZWriter::create(output_file_path)?;
-            let compression_level = compression_level.unwrap_or(5);
-
-            if let Some(pwd) = password {
-                writer.set_content_methods(vec![
-                    AesEncoderOptions::new(pwd.into()).into(),
-                    LZMA2Options::with_preset(compression_level).into(),
-                ]);
-            } else {
-                writer
-                    .set_content_methods(vec![LZMA2Options::with_preset(compression_level).into()]);
+
+            let mut methods = Vec::new();
+            if let Some(pwd) = options.password.as_deref() {
+                methods.push(AesEncoderOptions::new(pwd.into()).into());
+                writer.set_encrypt_header(options.encrypt_header);
+            }
+            methods.push(match options.level() {
+                0 => EncoderConfiguration::new(EncoderMethod::COPY),
+                level => LZMA2Options::with_preset(level).into(),
+            });
+            writer.set_content_methods(methods);
+
+            // Solid blocks like `push_source_path`, but reading through the monitor
+            let mut entries = Vec::new();
+            let mut readers = Vec::new();
+            let mut block_size = 0;
+            for (path, name, size) in files {
+                let entry = SevenZArchiveEntry::from_path(&path, name.clone());
+                let reader = MonitoredFile {
+                    path,
+                    name,
+                    file: None,
+                    done: false,
+                    monitor,
+                };
+                if !options.solid || size >= MAX_BLOCK_SIZE {
+                    writer.push_archive_entry(entry, Some(reader))?;
+                    continue;
+                }
+                if block_size + size >= MAX_BLOCK_SIZE {
+                    writer.push_archive_entries(
+                        std::mem::take(&mut entries),
+                        SeqReader::new(std::mem::take(&mut readers)),
+                    )?;
+                    block_size = 0;
+                }
+                block_size += size;
+                entries.push(entry);
+                readers.push(SourceReader::new(reader));
+            }
+            if !entries.is_empty() {
+                writer.push_archive_entries(entries, SeqReader::new(readers))?;
             }
 
-            writer.push_source_path(input_dir, |_| true)?;
             writer.finish()?;
 
             Ok(())
         }
 
-        compress_7z_dir_internal(input_dir, output_file_path, password, compression_level)
-            .map_err(|e| e.to_string())
+        compress_7z_dir_internal(files, output_file_path, options, monitor)
+            .map_err(|e| monitor.fail(e))?;
+        monitor.finish();
+        Ok(ArchiveBackend::Internal7z)
+    }
+}
+
+/// The header of a 7z archive, [None] if it can't be read
+fn read_7z_header(set: &VolumeSet, password: &Password) -> Option<sevenz_rust2::Archive> {
+    let archive = set
+        .open()
+        .map_err(sevenz_rust2::Error::from)
+        .and_then(|mut reader| sevenz_rust2::Archive::read(&mut reader, password));
+    match archive {
+        Ok(archive) => Some(archive),
+        Err(err) => {
+            warn!(
+                "Failed to read header of {}: {}",
+                set.first().display(),
+                err
+            );
+            None
+        }
+    }
+}
+
+/// Extract with the external 7z, which follows the volumes of a split archive from its entry.
+/// The password is piped, see [can_pipe_7z_password]
+fn decompress_7z_external(
+    external: &External7z,
+    input_file_path: &Path,
+    output_dir: &Path,
+    password: Option<&str>,
+    monitor: &FlateMonitor,
+) -> Result<(), FlateError> {
+    info!(
+        "Using external 7z command {} in decompressing {}",
+        external.program.display(),
+        input_file_path.display()
+    );
+
+    if !output_dir.exists() {
+        fs::create_dir_all(output_dir)?;
+    }
+
+    let mut command = external.command();
+    command.arg("x");
+    command.arg(input_file_path);
+    command.arg(format!("-o{}", output_dir.display()));
+    command.arg("-aoa");
+
+    if password.is_some() {
+        command.arg("-p");
     }
+
+    let input = password.map(|pwd| format!("{pwd}\n"));
+    run_7z_external(command, input, monitor)?;
+    check_extracted_links(output_dir)
 }
 
+/// Extract a 7z file, reading the volumes of a split one as a whole
 pub fn decompress_7z(
     input_file_path: impl AsRef<Path>,
     output_dir: impl AsRef<Path>,
     password: Option<&str>,
-) -> Result<(), String> {
-    if is_7z_in_path() {
+    monitor: &FlateMonitor,
+) -> Result<(), FlateError> {
+    decompress_7z_by(
+        select_7z(password)?,
+        input_file_path,
+        output_dir,
+        password,
+        monitor,
+    )
+}
+
+/// [decompress_7z] with the external 7z, or the internal library if [None]
+fn decompress_7z_by(
+    external: Option<External7z>,
+    input_file_path: impl AsRef<Path>,
+    output_dir: impl AsRef<Path>,
+    password: Option<&str>,
+    monitor: &FlateMonitor,
+) -> Result<(), FlateError> {
+    let set = volumes(input_file_path.as_ref())?;
+    let password_7z = password.map(Password::from).unwrap_or_else(Password::empty);
+    let header = read_7z_header(&set, &password_7z);
+    let totals = header.as_ref().map(|archive| {
+        let files = archive.files.iter().filter(|f| !f.is_directory());
+        files.fold((0, 0), |(bytes, count), f| (bytes + f.size(), count + 1))
+    });
+    monitor.begin(totals.map(|t| t.0), totals.map(|t| t.1));
+
+    if let Some(external) = external {
+        // Entries are checked up front, the symlinks 7z creates once it is done.
+        // Without a readable header nothing could be checked, so nothing is extracted
+        let Some(archive) = &header else {
+            return Err(FlateError::Unchecked(set.first().display().to_string()));
+        };
+        let entries = archive
+            .files
+            .iter()
+            .map(|file| (file.name(), is_7z_symlink(file)))
+            .collect::<Vec<_>>();
+        check_external_entries(output_dir.as_ref(), &entries)?;
+        decompress_7z_external(
+            &external,
+            set.entry(),
+            output_dir.as_ref(),
+            password,
+            monitor,
+        )
+    } else {
         info!(
-            "Using external 7z command in decompressing {}",
+            "Using internal 7z library in decompressing {}",
             input_file_path.as_ref().display()
         );
+        // Kept aside, as the extract fn can only return errors of sevenz_rust2
+        let rejected = RefCell::new(None);
+        let result = sevenz_rust2::decompress_with_extract_fn_and_password(
+            set.open()?,
+            &output_dir,
+            password_7z,
+            |entry, reader, dest| {
+                if let Err(err) = entry_path(output_dir.as_ref(), entry.name()) {
+                    let message = err.to_string();
+                    rejected.replace(Some(err));
+                    return Err(sevenz_rust2::Error::other(message));
+                }
+                if !entry.is_directory() {
+                    monitor
+                        .enter(entry.name())
+                        .map_err(|e| sevenz_rust2::Error::other(e.to_string()))?;
+                }
+                let mut reader = MonitoredRead {
+                    inner: reader,
+                    monitor,
+                };
+                let result = sevenz_rust2::default_entry_extract_fn(entry, &mut reader, dest);
+                if !entry.is_directory() {
+                    monitor.leave();
+                }
+                result
+            },
+        );
+        if let Some(err) = rejected.take() {
+            return Err(err);
+        }
+        result.map_err(|e| monitor.fail(e))?;
+        monitor.finish();
+        Ok(())
+    }
+}
 
-        fn decompress_7z_to_dir_external(
-            input_file_path: impl AsRef<Path>,
-            output_dir: impl AsRef<Path>,
-            password: Option<&str>,
-        ) -> Result<(), io::Error> {
-            if !output_dir.as_ref().exists() {
-                fs::create_dir_all(output_dir.as_ref())?;
-            }
+/// A tar stream over the volumes of the archive, decompressed as the format tells
+fn open_tar(
+    archive: &Path,
+    format: ArchiveFormat,
+) -> Result<tar::Archive<Box<dyn Read>>, FlateError> {
+    let reader = io::BufReader::new(volumes(archive)?.open()?);
+    let reader: Box<dyn Read> = match format {
+        ArchiveFormat::Tar => Box::new(reader),
+        ArchiveFormat::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+        ArchiveFormat::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+        ArchiveFormat::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader)),
+        other => return Err(unsupported(archive, Some(other))),
+    };
+    Ok(tar::Archive::new(reader))
+}
 
-            let mut command = create_hidden_command("7z");
-            command.arg("x");
-            command.arg(input_file_path.as_ref());
-            command.arg(format!("-o{}", output_dir.as_ref().display()));
-            command.arg("-aoa");
+/// Extract a tarball, plain or compressed with gzip, zstd or xz.
+///
+/// Totals are unknown before the whole stream is decompressed, so only the done ones are reported
+pub fn decompress_tar(
+    archive: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    monitor: &FlateMonitor,
+) -> Result<(), FlateError> {
+    let archive = archive.as_ref();
+    let format = ArchiveFormat::detect(archive)?;
+    let mut tar = open_tar(archive, format.ok_or_else(|| unsupported(archive, None))?)?;
 
-            if let Some(pwd) = password {
-                command.arg(format!("-p{pwd}"));
+    if !dst.as_ref().exists() {
+        fs::create_dir_all(&dst)?;
+    }
+    monitor.begin(None, None);
+
+    for entry in tar.entries()? {
+        let mut entry = entry.map_err(|e| monitor.fail(e))?;
+        let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+        entry_path(dst.as_ref(), &name)?;
+        let kind = entry.header().entry_type();
+        if let Some(link) = entry.link_name_bytes() {
+            let link = String::from_utf8_lossy(&link);
+            if kind.is_symlink() {
+                check_link(&name, &link)?;
+            } else if kind.is_hard_link() {
+                // Hard link targets are relative to the archive root
+                entry_path(dst.as_ref(), &link)?;
             }
+        }
+        let is_file = kind.is_file();
+        if is_file {
+            monitor.enter(entry.path()?.to_string_lossy())?;
+        }
+        entry.unpack_in(dst.as_ref()).map_err(|e| monitor.fail(e))?;
+        if is_file {
+            monitor.advance(entry.size());
+            monitor.leave();
+        }
+    }
 
-            let output = command.output()?;
+    monitor.finish();
+    Ok(())
+}
 
-            if !output.status.success() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "Fail in 7z command exec: {}",
-                        String::from_utf8_lossy(&output.stderr)
-                    ),
-                ));
-            }
+/// Formats an archive can be created in, see [compress]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Default)]
+pub enum CompressFormat {
+    #[default]
+    SevenZ,
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+    TarXz,
+}
 
-            Ok(())
+impl CompressFormat {
+    /// Extension of a created archive, without the leading dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::SevenZ => "7z",
+            Self::Zip => "zip",
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+            Self::TarZst => "tar.zst",
+            Self::TarXz => "tar.xz",
         }
+    }
 
-        decompress_7z_to_dir_external(input_file_path, output_dir, password)
-            .map_err(|e| e.to_string())
-    } else {
-        info!(
-            "Using internal 7z library in decompressing {}",
-            input_file_path.as_ref().display()
-        );
-        if let Some(pwd) = password {
-            sevenz_rust2::decompress_file_with_password(input_file_path, output_dir, pwd.into())
-                .map_err(|e| e.to_string())
+    /// Whether the archive can be encrypted with a password
+    pub fn supports_password(self) -> bool {
+        matches!(self, Self::SevenZ | Self::Zip)
+    }
+}
+
+/// How [compress] creates an archive, missing fields take their default
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[serde(default)]
+pub struct ArchiveOptions {
+    pub format: CompressFormat,
+    /// From 0 to store only up to 9, on the scale of 7z
+    pub level: u32,
+    /// Compress the files of a 7z as one block, which is smaller but slower to pick single files from
+    pub solid: bool,
+    /// Encrypts a 7z or zip, never serialized
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+    /// Encrypt the file names of a 7z as well, so listing needs the password
+    pub encrypt_header: bool,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        Self {
+            format: CompressFormat::default(),
+            level: 9,
+            solid: true,
+            password: None,
+            encrypt_header: false,
+        }
+    }
+}
+
+impl ArchiveOptions {
+    /// The level capped to 9
+    pub fn level(&self) -> u32 {
+        self.level.min(9)
+    }
+
+    /// Fails for options the format can't honor, before anything is written
+    pub fn check(&self) -> Result<(), FlateError> {
+        let unsupported = |what: &str| {
+            Err(FlateError::Unsupported(format!(
+                "{what} in a {} archive",
+                self.format.extension()
+            )))
+        };
+        if self.password.is_some() && !self.format.supports_password() {
+            return unsupported("password");
+        }
+        if self.encrypt_header && self.format != CompressFormat::SevenZ {
+            return unsupported("header encryption");
+        }
+        if self.encrypt_header && self.password.is_none() {
+            return unsupported("header encryption without a password");
+        }
+        Ok(())
+    }
+}
+
+/// Write the files into a tar stream, reading them through the monitor
+fn write_tar<W: Write>(
+    files: Vec<(PathBuf, String, u64)>,
+    writer: W,
+    monitor: &FlateMonitor,
+) -> Result<W, FlateError> {
+    let mut builder = tar::Builder::new(writer);
+    for (path, name, size) in files {
+        monitor.enter(&name)?;
+        let file = File::open(&path)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(&file.metadata()?);
+        header.set_size(size);
+        let reader = MonitoredRead {
+            inner: file,
+            monitor,
+        };
+        builder
+            .append_data(&mut header, &name, reader)
+            .map_err(|e| monitor.fail(e))?;
+        monitor.leave();
+    }
+    Ok(builder.into_inner()?)
+}
+
+/// Compress a directory to a tarball, the level is on the 0-9 scale of 7z and ignored by plain tar
+pub fn compress_tar(
+    input_dir: impl AsRef<Path>,
+    output_file_path: impl AsRef<Path>,
+    format: CompressFormat,
+    compression_level: Option<u32>,
+    monitor: &FlateMonitor,
+) -> Result<(), FlateError> {
+    let files = collect_files(input_dir.as_ref())?;
+    monitor.begin(
+        Some(files.iter().map(|(_, _, size)| size).sum()),
+        Some(files.len() as u64),
+    );
+    info!(
+        "Compressing {} to {}",
+        input_dir.as_ref().display(),
+        format.extension()
+    );
+
+    let level = compression_level.unwrap_or(5).min(9);
+    let out = io::BufWriter::new(File::create(output_file_path)?);
+    let out = match format {
+        CompressFormat::Tar => write_tar(files, out, monitor)?,
+        CompressFormat::TarGz => {
+            let encoder = flate2::write::GzEncoder::new(out, flate2::Compression::new(level));
+            write_tar(files, encoder, monitor)?.finish()?
+        }
+        CompressFormat::TarZst => {
+            // Twice the level, so 9 lands close to the strongest regular level 19
+            let encoder = zstd::Encoder::new(out, (level * 2 + 1) as i32)?;
+            write_tar(files, encoder, monitor)?.finish()?
+        }
+        CompressFormat::TarXz => {
+            let encoder = xz2::write::XzEncoder::new(out, level);
+            write_tar(files, encoder, monitor)?.finish()?
+        }
+        other => {
+            return Err(FlateError::Unsupported(format!(
+                "{} as a tarball",
+                other.extension()
+            )));
+        }
+    };
+    out.into_inner().map_err(io::IntoInnerError::into_error)?;
+
+    monitor.finish();
+    Ok(())
+}
+
+/// Compress a directory to a zip file, deflated unless the level is 0, AES-256 with a password
+pub fn compress_zip(
+    input_dir: impl AsRef<Path>,
+    output_file_path: impl AsRef<Path>,
+    options: &ArchiveOptions,
+    monitor: &FlateMonitor,
+) -> Result<(), FlateError> {
+    use zip::write::SimpleFileOptions;
+    use zip::{AesMode, CompressionMethod, ZipWriter};
+
+    let files = collect_files(input_dir.as_ref())?;
+    monitor.begin(
+        Some(files.iter().map(|(_, _, size)| size).sum()),
+        Some(files.len() as u64),
+    );
+    info!("Compressing {} to zip", input_dir.as_ref().display());
+
+    let mut file_options = match options.level() {
+        0 => SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+        level => SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(Some(level.into())),
+    };
+    if let Some(pwd) = options.password.as_deref() {
+        file_options = file_options.with_aes_encryption(AesMode::Aes256, pwd);
+    }
+
+    let mut writer = ZipWriter::new(io::BufWriter::new(File::create(output_file_path)?));
+    for (path, name, size) in files {
+        monitor.enter(&name)?;
+        writer.start_file(
+            name.replace('\\', "/"),
+            file_options.large_file(size >= u32::MAX as u64),
+        )?;
+        let mut reader = MonitoredRead {
+            inner: File::open(&path)?,
+            monitor,
+        };
+        io::copy(&mut reader, &mut writer).map_err(|e| monitor.fail(e))?;
+        monitor.leave();
+    }
+    writer
+        .finish()?
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?;
+
+    monitor.finish();
+    Ok(())
+}
+
+/// Compress a directory in the format of the options, failing before writing anything
+/// if the format can't honor them, see [ArchiveOptions::check].
+/// Returns the backend that wrote the archive
+pub fn compress(
+    input_dir: impl AsRef<Path>,
+    output_file_path: impl AsRef<Path>,
+    options: &ArchiveOptions,
+    monitor: &FlateMonitor,
+) -> Result<ArchiveBackend, FlateError> {
+    options.check()?;
+    let backend = match options.format {
+        CompressFormat::SevenZ => compress_7z(&input_dir, &output_file_path, options, monitor)?,
+        CompressFormat::Zip => {
+            compress_zip(&input_dir, &output_file_path, options, monitor)?;
+            ArchiveBackend::Zip
+        }
+        format => {
+            compress_tar(
+                &input_dir,
+                &output_file_path,
+                format,
+                Some(options.level()),
+                monitor,
+            )?;
+            ArchiveBackend::Tar
+        }
+    };
+    info!(
+        "Created {} with {:?}",
+        output_file_path.as_ref().display(),
+        backend
+    );
+    Ok(backend)
+}
+
+/// Archive formats told apart by their magic bytes, see [ArchiveFormat::detect].
+/// A compressed stream is taken as a tarball, see [ArchiveFormat::is_tarball]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    /// Both RAR 4 and RAR 5
+    Rar,
+    SevenZ,
+    Tar,
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+/// Extensions of archive files, such a file holding none of the known formats is broken
+const ARCHIVE_EXTENSIONS: [&str; 10] = [
+    "zip", "rar", "7z", "tar", "gz", "tgz", "zst", "tzst", "xz", "txz",
+];
+/// Where the `ustar` magic of a tar header starts
+const TAR_MAGIC_OFFSET: usize = 257;
+
+impl ArchiveFormat {
+    /// Detect the format from the start of the file, [None] if it is not a known archive.
+    /// Any volume of a split archive is detected by the first one
+    pub fn detect(path: impl AsRef<Path>) -> Result<Option<Self>, io::Error> {
+        let set = VolumeSet::find(path)?;
+        let mut head = Vec::with_capacity(TAR_MAGIC_OFFSET + 5);
+        File::open(set.first())?
+            .take((TAR_MAGIC_OFFSET + 5) as u64)
+            .read_to_end(&mut head)?;
+        Ok(Self::from_magic(&head))
+    }
+
+    fn from_magic(head: &[u8]) -> Option<Self> {
+        if [b"PK\x03\x04", b"PK\x05\x06", b"PK\x07\x08"]
+            .iter()
+            .any(|magic| head.starts_with(*magic))
+        {
+            Some(Self::Zip)
+        } else if head.starts_with(b"Rar!\x1A\x07\x00") || head.starts_with(b"Rar!\x1A\x07\x01\x00")
+        {
+            Some(Self::Rar)
+        } else if head.starts_with(b"7z\xBC\xAF\x27\x1C") {
+            Some(Self::SevenZ)
+        } else if head.starts_with(b"\x1F\x8B") {
+            Some(Self::Gzip)
+        } else if head.starts_with(b"\x28\xB5\x2F\xFD") {
+            Some(Self::Zstd)
+        } else if head.starts_with(b"\xFD7zXZ\x00") {
+            Some(Self::Xz)
+        } else if head.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + 5) == Some(b"ustar") {
+            Some(Self::Tar)
         } else {
-            sevenz_rust2::decompress_file(input_file_path, output_dir).map_err(|e| e.to_string())
+            None
+        }
+    }
+
+    /// Whether the file is named like an archive or one of its volumes, whatever it holds
+    pub fn has_archive_extension(path: impl AsRef<Path>) -> bool {
+        if volume::is_volume_name(&path) {
+            return true;
+        }
+        let extension = path
+            .as_ref()
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_lowercase();
+        ARCHIVE_EXTENSIONS.contains(&extension.as_str())
+    }
+
+    /// Whether the format can be encrypted with a password
+    pub fn supports_password(self) -> bool {
+        matches!(self, Self::Zip | Self::Rar | Self::SevenZ)
+    }
+
+    /// Whether the format is a tar stream, plain or compressed, see [decompress_tar]
+    pub fn is_tarball(self) -> bool {
+        matches!(self, Self::Tar | Self::Gzip | Self::Zstd | Self::Xz)
+    }
+}
+
+fn unsupported(archive: &Path, format: Option<ArchiveFormat>) -> FlateError {
+    FlateError::Unsupported(match format {
+        Some(format) => format!("{format:?} in {}", archive.display()),
+        None => archive.display().to_string(),
+    })
+}
+
+/// Decode the first entry holding data, which needs the password if the archive has one
+fn read_first_7z_entry(set: &VolumeSet, password: Password) -> Result<(), sevenz_rust2::Error> {
+    let mut reader = sevenz_rust2::SevenZReader::new(set.open()?, password)?;
+    reader.for_each_entries(|entry, reader| {
+        if !entry.has_stream() {
+            return Ok(true);
+        }
+        io::copy(reader, &mut io::sink())?;
+        Ok(false)
+    })
+}
+
+/// Decode the smallest encrypted entry, the CRC catches a wrong password passing the header check
+fn check_zip_password(zip: &Path, password: &str) -> Result<(), FlateError> {
+    let mut archive = open_zip(zip)?;
+    let mut smallest = None;
+    for cur in 0..archive.len() {
+        let file = archive.by_index_raw(cur)?;
+        if file.encrypted() && smallest.is_none_or(|(_, size)| file.size() < size) {
+            smallest = Some((cur, file.size()));
+        }
+    }
+    if let Some((cur, _)) = smallest {
+        let mut file = archive.by_index_decrypt(cur, password.as_bytes())?;
+        io::copy(&mut file, &mut io::sink())?;
+    }
+    Ok(())
+}
+
+/// Test the first encrypted file, nothing is written
+fn check_rar_password(rar: &Path, password: &str) -> Result<(), UnrarError> {
+    let mut archive = rar_archive(rar, Some(password)).open_for_processing()?;
+    while let Some(header) = archive.read_header()? {
+        if header.entry().is_file() && header.entry().is_encrypted() {
+            header.test()?;
+            break;
+        }
+        archive = header.skip()?;
+    }
+    Ok(())
+}
+
+fn rar_is_encrypted(rar: &Path) -> Result<bool, UnrarError> {
+    for header in rar_archive(rar, None).open_for_listing()? {
+        if header?.is_encrypted() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Whether extracting the archive needs a password, told from its headers where possible
+pub fn is_encrypted(archive: impl AsRef<Path>) -> Result<bool, FlateError> {
+    let archive = archive.as_ref();
+    match ArchiveFormat::detect(archive)? {
+        Some(ArchiveFormat::Zip) => {
+            let mut zip = open_zip(archive)?;
+            for cur in 0..zip.len() {
+                if zip.by_index_raw(cur)?.encrypted() {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        // Encrypted headers can't even be listed without one
+        Some(ArchiveFormat::Rar) => match rar_is_encrypted(volumes(archive)?.entry()) {
+            Err(err) if err.code == unrar::error::Code::MissingPassword => Ok(true),
+            result => Ok(result?),
+        },
+        // The AES decoder refuses to start without a password
+        Some(ArchiveFormat::SevenZ) => {
+            match read_first_7z_entry(&volumes(archive)?, Password::empty()) {
+                Ok(()) => Ok(false),
+                Err(sevenz_rust2::Error::PasswordRequired) => Ok(true),
+                Err(err) => Err(err.into()),
+            }
+        }
+        Some(format) if format.is_tarball() => Ok(false),
+        other => Err(unsupported(archive, other)),
+    }
+}
+
+/// Whether the password opens the archive, by decoding a single entry instead of extracting
+pub fn check_password(archive: impl AsRef<Path>, password: &str) -> bool {
+    let archive = archive.as_ref();
+    let result = match ArchiveFormat::detect(archive) {
+        Ok(Some(ArchiveFormat::Zip)) => check_zip_password(archive, password),
+        Ok(Some(ArchiveFormat::Rar)) => volumes(archive)
+            .and_then(|set| check_rar_password(set.entry(), password).map_err(FlateError::from)),
+        Ok(Some(ArchiveFormat::SevenZ)) => volumes(archive).and_then(|set| {
+            read_first_7z_entry(&set, Password::from(password)).map_err(FlateError::from)
+        }),
+        Ok(other) => Err(unsupported(archive, other)),
+        Err(err) => Err(err.into()),
+    };
+    // A damaged archive fails the same way as a wrong password
+    if let Err(err) = &result {
+        debug!("Password rejected by {}: {}", archive.display(), err);
+    }
+    result.is_ok()
+}
+
+/// Try the candidates on an archive, [None] if it is not encrypted at all.
+/// Fails with [FlateError::PasswordNotFound] when no candidate opens an encrypted one
+pub fn find_password(
+    archive: impl AsRef<Path>,
+    candidates: &[String],
+    monitor: &FlateMonitor,
+) -> Result<Option<String>, FlateError> {
+    let archive = archive.as_ref();
+    if !is_encrypted(archive)? {
+        return Ok(None);
+    }
+    for candidate in candidates {
+        monitor.check()?;
+        if check_password(archive, candidate) {
+            info!("Found the password of {}", archive.display());
+            return Ok(Some(candidate.clone()));
         }
     }
+    warn!(
+        "None of {} password candidates opens {}",
+        candidates.len(),
+        archive.display()
+    );
+    Err(FlateError::PasswordNotFound(candidates.len()))
+}
+
+/// A zip timestamp, stored without a timezone
+fn zip_time(time: zip::DateTime) -> Option<DateTime<Utc>> {
+    NaiveDate::from_ymd_opt(time.year().into(), time.month().into(), time.day().into())?
+        .and_hms_opt(
+            time.hour().into(),
+            time.minute().into(),
+            time.second().into(),
+        )
+        .map(|time| time.and_utc())
+}
+
+/// A rar timestamp in the MS-DOS format, stored without a timezone
+fn dos_time(time: u32) -> Option<DateTime<Utc>> {
+    let (date, time) = (time >> 16, time & 0xFFFF);
+    NaiveDate::from_ymd_opt(1980 + (date >> 9) as i32, (date >> 5) & 0x0F, date & 0x1F)?
+        .and_hms_opt(time >> 11, (time >> 5) & 0x3F, (time & 0x1F) * 2)
+        .map(|time| time.and_utc())
+}
+
+/// List the files of an archive without extracting anything, a compressed tarball is
+/// decompressed on the way.
+///
+/// The password is only needed for archives with encrypted headers
+pub fn list_entries(
+    archive: impl AsRef<Path>,
+    password: Option<&str>,
+) -> Result<Vec<ArchiveEntry>, FlateError> {
+    let archive = archive.as_ref();
+    let mut entries = Vec::new();
+    match ArchiveFormat::detect(archive)? {
+        Some(ArchiveFormat::Zip) => {
+            let mut zip = open_zip(archive)?;
+            for cur in 0..zip.len() {
+                let file = zip.by_index_raw(cur)?;
+                if file.is_dir() {
+                    continue;
+                }
+                entries.push(ArchiveEntry {
+                    path: file.name().to_string(),
+                    size: file.size(),
+                    compressed_size: Some(file.compressed_size()),
+                    modified: file.last_modified().and_then(zip_time),
+                    encrypted: file.encrypted(),
+                });
+            }
+        }
+        Some(ArchiveFormat::Rar) => {
+            let set = volumes(archive)?;
+            for header in rar_archive(set.entry(), password).open_for_listing()? {
+                let header = header?;
+                if !header.is_file() {
+                    continue;
+                }
+                entries.push(ArchiveEntry {
+                    path: header.filename.to_string_lossy().replace('\\', "/"),
+                    size: header.unpacked_size,
+                    compressed_size: None,
+                    modified: dos_time(header.file_time),
+                    encrypted: header.is_encrypted(),
+                });
+            }
+        }
+        Some(ArchiveFormat::SevenZ) => {
+            // Encryption applies to whole blocks, so it is told for the archive at once
+            let encrypted = is_encrypted(archive)?;
+            let password = password.map(Password::from).unwrap_or_else(Password::empty);
+            let seven = sevenz_rust2::Archive::read(&mut volumes(archive)?.open()?, &password)?;
+            for file in seven.files.iter().filter(|f| !f.is_directory()) {
+                entries.push(ArchiveEntry {
+                    path: file.name().to_string(),
+                    size: file.size(),
+                    compressed_size: (!seven.is_solid).then_some(file.compressed_size),
+                    modified: file
+                        .has_last_modified_date
+                        .then(|| SystemTime::from(file.last_modified_date).into()),
+                    encrypted: encrypted && file.has_stream(),
+                });
+            }
+        }
+        Some(format) if format.is_tarball() => {
+            for entry in open_tar(archive, format)?.entries()? {
+                let entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                entries.push(ArchiveEntry {
+                    path: entry.path()?.to_string_lossy().replace('\\', "/"),
+                    size: entry.size(),
+                    compressed_size: None,
+                    modified: entry
+                        .header()
+                        .mtime()
+                        .ok()
+                        .and_then(|mtime| DateTime::from_timestamp(mtime as i64, 0)),
+                    encrypted: false,
+                });
+            }
+        }
+        other => return Err(unsupported(archive, other)),
+    }
+    Ok(entries)
+}
+
+/// Read a single file out of an archive by its path from [list_entries], nothing is written.
+///
+/// A solid 7z or a compressed tarball is decoded up to the file
+pub fn read_entry(
+    archive: impl AsRef<Path>,
+    name: &str,
+    password: Option<&str>,
+) -> Result<Vec<u8>, FlateError> {
+    let archive = archive.as_ref();
+    let mut data = Vec::new();
+    let found = match ArchiveFormat::detect(archive)? {
+        Some(ArchiveFormat::Zip) => {
+            let mut zip = open_zip(archive)?;
+            let mut file = match password {
+                Some(pwd) => zip.by_name_decrypt(name, pwd.as_bytes())?,
+                None => zip.by_name(name)?,
+            };
+            file.read_to_end(&mut data)?;
+            true
+        }
+        Some(ArchiveFormat::Rar) => {
+            let set = volumes(archive)?;
+            let mut rar = rar_archive(set.entry(), password).open_for_processing()?;
+            let mut found = false;
+            while let Some(header) = rar.read_header()? {
+                let path = header.entry().filename.to_string_lossy().replace('\\', "/");
+                if header.entry().is_file() && path == name {
+                    data = header.read()?.0;
+                    found = true;
+                    break;
+                }
+                rar = header.skip()?;
+            }
+            found
+        }
+        Some(ArchiveFormat::SevenZ) => {
+            let password = password.map(Password::from).unwrap_or_else(Password::empty);
+            let mut reader = sevenz_rust2::SevenZReader::new(volumes(archive)?.open()?, password)?;
+            let mut found = false;
+            reader.for_each_entries(|entry, reader| {
+                if entry.is_directory() || entry.name() != name {
+                    return Ok(true);
+                }
+                reader.read_to_end(&mut data)?;
+                found = true;
+                Ok(false)
+            })?;
+            found
+        }
+        Some(format) if format.is_tarball() => {
+            let mut found = false;
+            for entry in open_tar(archive, format)?.entries()? {
+                let mut entry = entry?;
+                if entry.header().entry_type().is_file()
+                    && entry.path()?.to_string_lossy().replace('\\', "/") == name
+                {
+                    entry.read_to_end(&mut data)?;
+                    found = true;
+                    break;
+                }
+            }
+            found
+        }
+        other => return Err(unsupported(archive, other)),
+    };
+    if !found {
+        return Err(FlateError::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{name} not found in {}", archive.display()),
+        )));
+    }
+    Ok(data)
 }
 
 #[cfg(test)]
 mod test {
-    use crate::util::file::{cd_test, cd_test_clear};
-    use std::fs;
-    use std::fs::File;
+    use super::*;
+    use crate::util::file::cd_test_dir;
     use std::io::Write;
-    use std::path::Path;
+
+    fn with_password(password: &str) -> ArchiveOptions {
+        ArchiveOptions {
+            password: Some(password.to_string()),
+            ..Default::default()
+        }
+    }
 
     fn create_test_files(base_path: &Path) -> std::io::Result<()> {
         // 创建多层嵌套目录
@@ -292,35 +1875,575 @@ mod test {
 
     #[test]
     fn test_7z() {
-        let origin_path = cd_test().join("7z_test");
-        if origin_path.exists() {
-            fs::remove_dir_all(&origin_path).expect("Failed to remove origin path");
-        }
+        let test_dir = cd_test_dir("7z");
+        let origin_path = test_dir.join("7z_test");
         fs::create_dir_all(&origin_path).expect("Error creating origin path");
 
         create_test_files(&origin_path).unwrap();
-        let out = cd_test().join("7z_test.7z");
+        let out = test_dir.join("7z_test.7z");
         if out.exists() {
             fs::remove_file(&out).expect("Failed to remove output file");
         }
 
         println!("Test no password");
-        let result = super::compress_7z(&origin_path, &out, None, None);
+        let result = compress_7z(
+            &origin_path,
+            &out,
+            &ArchiveOptions::default(),
+            &FlateMonitor::silent(),
+        );
         assert!(result.is_ok(), "Failed to compress directory: {:?}", result);
 
-        let out_pwd = cd_test().join("7z_test_pwd.7z");
+        let out_pwd = test_dir.join("7z_test_pwd.7z");
         if out_pwd.exists() {
             fs::remove_file(&out_pwd).expect("Failed to remove output file");
         }
 
         println!("Test with password");
-        let result = super::compress_7z(&origin_path, &out_pwd, Some("中文密码"), Some(9));
+        let result = compress_7z(
+            &origin_path,
+            &out_pwd,
+            &with_password("中文密码"),
+            &FlateMonitor::silent(),
+        );
         assert!(
             result.is_ok(),
             "Failed to compress directory with password: {:?}",
             result
         );
 
-        cd_test_clear();
+        println!("Test decompress with password");
+        let extracted = test_dir.join("7z_test_pwd");
+        decompress_7z(
+            &out_pwd,
+            &extracted,
+            Some("中文密码"),
+            &FlateMonitor::silent(),
+        )
+        .expect("Failed to decompress with password");
+        assert_eq!(
+            fs::read(extracted.join("dir1/file2.txt")).unwrap(),
+            b"TestFile2 in dir1"
+        );
+        let result = decompress_7z(
+            &out_pwd,
+            test_dir.join("7z_test_wrong"),
+            Some("wrong"),
+            &FlateMonitor::silent(),
+        );
+        assert!(result.is_err(), "Decompressed with a wrong password");
+
+        println!("Test with a password full of shell characters");
+        let pwd = "p@ss \"$(x)\" -y;|&";
+        let out_ascii = test_dir.join("7z_test_ascii.7z");
+        compress_7z(
+            &origin_path,
+            &out_ascii,
+            &with_password(pwd),
+            &FlateMonitor::silent(),
+        )
+        .expect("Failed to compress with password");
+        let extracted = test_dir.join("7z_test_ascii");
+        decompress_7z(&out_ascii, &extracted, Some(pwd), &FlateMonitor::silent())
+            .expect("Failed to decompress with password");
+        assert_eq!(fs::read(extracted.join("file1.txt")).unwrap(), b"TestFile1");
+
+        fs::remove_dir_all(&test_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_find_password() {
+        let test_dir = cd_test_dir("7z_find_password");
+        let origin_path = test_dir.join("origin");
+        fs::create_dir_all(&origin_path).unwrap();
+        create_test_files(&origin_path).unwrap();
+        let plain = test_dir.join("plain.7z");
+        let locked = test_dir.join("locked.7z");
+        let monitor = FlateMonitor::silent();
+        compress_7z(&origin_path, &plain, &ArchiveOptions::default(), &monitor).unwrap();
+        compress_7z(&origin_path, &locked, &with_password("中文密码"), &monitor).unwrap();
+
+        assert!(!is_encrypted(&plain).unwrap());
+        assert!(is_encrypted(&locked).unwrap());
+        assert!(!check_password(&locked, "wrong"));
+        assert!(check_password(&locked, "中文密码"));
+
+        let candidates = vec!["wrong".to_string(), "中文密码".to_string()];
+        assert_eq!(find_password(&plain, &candidates, &monitor).unwrap(), None);
+        assert_eq!(
+            find_password(&locked, &candidates, &monitor).unwrap(),
+            Some("中文密码".to_string())
+        );
+        assert!(matches!(
+            find_password(&locked, &candidates[..1], &monitor),
+            Err(FlateError::PasswordNotFound(1))
+        ));
+
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_list_entries() {
+        let test_dir = cd_test_dir("7z_list_entries");
+        let origin_path = test_dir.join("origin");
+        fs::create_dir_all(&origin_path).unwrap();
+        create_test_files(&origin_path).unwrap();
+        let out = test_dir.join("list.7z");
+        compress_7z(
+            &origin_path,
+            &out,
+            &ArchiveOptions::default(),
+            &FlateMonitor::silent(),
+        )
+        .unwrap();
+
+        let entries = list_entries(&out, None).unwrap();
+        assert_eq!(entries.len(), 5);
+        let file2 = entries.iter().find(|e| e.path == "dir1/file2.txt").unwrap();
+        assert_eq!(file2.size, 17);
+        assert!(!file2.encrypted);
+        assert!(file2.modified.is_some());
+
+        // 2024-05-17 13:45:30
+        let time = dos_time((44 << 25) | (5 << 21) | (17 << 16) | (13 << 11) | (45 << 5) | 15);
+        assert_eq!(time.unwrap().to_rfc3339(), "2024-05-17T13:45:30+00:00");
+        assert_eq!(dos_time(0), None);
+
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_tar() {
+        let test_dir = cd_test_dir("tar");
+        let origin_path = test_dir.join("origin");
+        fs::create_dir_all(&origin_path).unwrap();
+        create_test_files(&origin_path).unwrap();
+
+        for (format, detected) in [
+            (CompressFormat::Tar, ArchiveFormat::Tar),
+            (CompressFormat::TarGz, ArchiveFormat::Gzip),
+            (CompressFormat::TarZst, ArchiveFormat::Zstd),
+            (CompressFormat::TarXz, ArchiveFormat::Xz),
+        ] {
+            let out = test_dir.join(format!("test.{}", format.extension()));
+            let monitor = FlateMonitor::silent();
+            compress_tar(&origin_path, &out, format, None, &monitor).unwrap();
+            assert_eq!(monitor.progress().entries_done, 5);
+            assert_eq!(ArchiveFormat::detect(&out).unwrap(), Some(detected));
+            assert!(!is_encrypted(&out).unwrap());
+
+            let entries = list_entries(&out, None).unwrap();
+            assert_eq!(entries.len(), 5, "{format:?}");
+            let file4 = entries
+                .iter()
+                .find(|e| e.path == "dir2/sub_dir2/file4.txt")
+                .unwrap();
+            assert_eq!(file4.size, 1024 * 5);
+            assert!(file4.modified.is_some());
+
+            let extracted = test_dir.join(format!("extracted_{format:?}"));
+            let monitor = FlateMonitor::silent();
+            decompress_tar(&out, &extracted, &monitor).unwrap();
+            assert_eq!(monitor.progress().bytes_done, 9 + 17 + 1024 * 26);
+            assert_eq!(
+                fs::read(extracted.join("dir1/file2.txt")).unwrap(),
+                b"TestFile2 in dir1"
+            );
+        }
+
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_zip() {
+        let test_dir = cd_test_dir("zip");
+        let origin_path = test_dir.join("origin");
+        fs::create_dir_all(&origin_path).unwrap();
+        create_test_files(&origin_path).unwrap();
+
+        let stored = test_dir.join("stored.zip");
+        let options = ArchiveOptions {
+            format: CompressFormat::Zip,
+            level: 0,
+            ..Default::default()
+        };
+        compress(&origin_path, &stored, &options, &FlateMonitor::silent()).unwrap();
+        let entries = list_entries(&stored, None).unwrap();
+        assert_eq!(entries.len(), 5);
+        assert!(entries.iter().all(|e| e.compressed_size == Some(e.size)));
+        assert!(!is_encrypted(&stored).unwrap());
+
+        let locked = test_dir.join("locked.zip");
+        let options = ArchiveOptions {
+            format: CompressFormat::Zip,
+            ..with_password("中文密码")
+        };
+        let monitor = FlateMonitor::silent();
+        compress(&origin_path, &locked, &options, &monitor).unwrap();
+        assert_eq!(monitor.progress().bytes_done, 9 + 17 + 1024 * 26);
+        assert!(is_encrypted(&locked).unwrap());
+        assert!(!check_password(&locked, "wrong"));
+        let extracted = test_dir.join("extracted");
+        decompress_zip(
+            &locked,
+            &extracted,
+            Some("中文密码"),
+            &FlateMonitor::silent(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read(extracted.join("dir2/sub_dir2/file4.txt")).unwrap(),
+            vec![b'B'; 1024 * 5]
+        );
+        assert_eq!(
+            read_entry(&locked, "dir1/file2.txt", Some("中文密码")).unwrap(),
+            b"TestFile2 in dir1"
+        );
+        assert!(read_entry(&stored, "missing.txt", None).is_err());
+
+        // Options the format can't honor fail before anything is written
+        let rejected = test_dir.join("rejected.tar.zst");
+        let options = ArchiveOptions {
+            format: CompressFormat::TarZst,
+            ..with_password("pwd")
+        };
+        let result = compress(&origin_path, &rejected, &options, &FlateMonitor::silent());
+        assert!(matches!(result, Err(FlateError::Unsupported(_))));
+        let options = ArchiveOptions {
+            encrypt_header: true,
+            ..Default::default()
+        };
+        assert!(options.check().is_err());
+        assert!(!rejected.exists());
+
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
+
+    /// Append an entry with raw names, which the tar builder would refuse
+    fn append_raw_tar(
+        builder: &mut tar::Builder<File>,
+        name: &str,
+        kind: tar::EntryType,
+        link: &str,
+        data: &[u8],
+    ) {
+        let mut header = tar::Header::new_ustar();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.as_old_mut().linkname[..link.len()].copy_from_slice(link.as_bytes());
+        header.set_entry_type(kind);
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+        builder.append(&header, data).unwrap();
+    }
+
+    #[test]
+    fn test_unsafe_paths() {
+        let test_dir = cd_test_dir("unsafe_paths");
+        let dst = test_dir.join("dst");
+        for name in [
+            "../evil.txt",
+            "a/../../evil.txt",
+            "/etc/passwd",
+            "C:\\evil",
+            "..\\evil",
+        ] {
+            assert!(matches!(
+                entry_path(&dst, name),
+                Err(FlateError::UnsafePath(_))
+            ));
+        }
+        assert_eq!(
+            entry_path(&dst, "a/./b\\c.txt").unwrap(),
+            dst.join("a/b/c.txt")
+        );
+        assert!(check_link("lib/libfoo.so", "libfoo.so.1").is_ok());
+        assert!(check_link("a/b/link", "../../c").is_ok());
+        assert!(check_link("link", "../outside").is_err());
+        assert!(check_link("a/link", "b/../../..").is_err());
+        assert!(check_link("link", "/etc/passwd").is_err());
+
+        let malicious = [
+            ("traversal", "../evil.txt", tar::EntryType::Regular, ""),
+            ("absolute", "link", tar::EntryType::Symlink, "/etc"),
+            ("climbing", "a/link", tar::EntryType::Symlink, "../../.."),
+            ("hard", "hard", tar::EntryType::Link, "../evil.txt"),
+        ];
+        for (case, name, kind, link) in malicious {
+            let archive = test_dir.join(format!("{case}.tar"));
+            let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+            append_raw_tar(&mut builder, name, kind, link, b"evil");
+            builder.finish().unwrap();
+            let result = decompress_tar(&archive, &dst, &FlateMonitor::silent());
+            assert!(
+                matches!(result, Err(FlateError::UnsafePath(_))),
+                "{case}: {result:?}"
+            );
+        }
+
+        // A safe symlink is kept, but nothing is written through it
+        if cfg!(unix) {
+            let archive = test_dir.join("through.tar");
+            let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+            append_raw_tar(&mut builder, "link", tar::EntryType::Symlink, ".", b"");
+            append_raw_tar(
+                &mut builder,
+                "link/x.txt",
+                tar::EntryType::Regular,
+                "",
+                b"x",
+            );
+            builder.finish().unwrap();
+            let through = test_dir.join("through");
+            let result = decompress_tar(&archive, &through, &FlateMonitor::silent());
+            assert!(matches!(result, Err(FlateError::UnsafePath(_))));
+            assert!(through.join("link").is_symlink());
+        }
+
+        let archive = test_dir.join("evil.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("../evil.txt", options).unwrap();
+        zip.write_all(b"evil").unwrap();
+        zip.finish().unwrap();
+        let result = decompress_zip(&archive, &dst, None, &FlateMonitor::silent());
+        assert!(matches!(result, Err(FlateError::UnsafePath(_))));
+
+        let archive = test_dir.join("link.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+        zip.add_symlink("link", "../../etc", options).unwrap();
+        zip.finish().unwrap();
+        let result = decompress_zip(&archive, &dst, None, &FlateMonitor::silent());
+        assert!(matches!(result, Err(FlateError::UnsafePath(_))));
+
+        // The external 7z is never run, names are checked from the header before
+        let archive = test_dir.join("evil.7z");
+        let mut writer = sevenz_rust2::SevenZWriter::create(&archive).unwrap();
+        writer
+            .push_archive_entry(
+                sevenz_rust2::SevenZArchiveEntry::new_file("../evil.txt"),
+                Some(&b"evil"[..]),
+            )
+            .unwrap();
+        writer.finish().unwrap();
+        let broken = test_dir.join("broken.7z");
+        fs::write(&broken, b"7z\xBC\xAF\x27\x1C\x00\x04not a header").unwrap();
+        let external = || {
+            Some(External7z {
+                program: PathBuf::from("/nonexistent/7z"),
+                banner: String::new(),
+            })
+        };
+        let cases = [
+            ("internal", None, &archive),
+            ("external", external(), &archive),
+        ];
+        for (case, backend, archive) in cases {
+            let result = decompress_7z_by(backend, archive, &dst, None, &FlateMonitor::silent());
+            assert!(
+                matches!(result, Err(FlateError::UnsafePath(_))),
+                "{case}: {result:?}"
+            );
+        }
+        let result = decompress_7z_by(external(), &broken, &dst, None, &FlateMonitor::silent());
+        assert!(matches!(result, Err(FlateError::Unchecked(_))));
+
+        // A file under a symlink entry is rejected from the unix mode in the attributes
+        let linked = test_dir.join("linked.7z");
+        let mut writer = sevenz_rust2::SevenZWriter::create(&linked).unwrap();
+        let mut link = sevenz_rust2::SevenZArchiveEntry::new_file("link");
+        link.has_windows_attributes = true;
+        link.windows_attributes = 0x8000 | (0o120777 << 16);
+        writer.push_archive_entry(link, Some(&b"/etc"[..])).unwrap();
+        writer
+            .push_archive_entry(
+                sevenz_rust2::SevenZArchiveEntry::new_file("link/evil.txt"),
+                Some(&b"evil"[..]),
+            )
+            .unwrap();
+        writer.finish().unwrap();
+        let result = decompress_7z_by(external(), &linked, &dst, None, &FlateMonitor::silent());
+        assert!(
+            matches!(result, Err(FlateError::UnsafePath(_))),
+            "{result:?}"
+        );
+        assert!(decompress_7z_by(None, &broken, &dst, None, &FlateMonitor::silent()).is_err());
+
+        // No RAR can be crafted, unrar only reads them. decompress_rar checks every name with
+        // entry_path before extracting it, like the zip and tar cases above
+
+        assert!(!test_dir.join("evil.txt").exists());
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_external_entries() {
+        let listing = "\
+Listing archive: spanned.zip
+
+--
+Path = spanned.zip
+Type = zip
+
+----------
+Path = dir
+Folder = +
+Attributes = D_ drwxr-xr-x
+
+Path = dir/link
+Folder = -
+Attributes = _ lrwxrwxrwx
+
+Path = dir/link/evil.txt
+Folder = -
+Attributes = _ -rw-r--r--
+";
+        let entries = parse_7z_listing(listing);
+        assert_eq!(
+            entries,
+            [
+                ("dir".to_string(), false),
+                ("dir/link".to_string(), true),
+                ("dir/link/evil.txt".to_string(), false),
+            ]
+        );
+
+        let dst = Path::new("out");
+        let check = |entries: &[(&str, bool)]| check_external_entries(dst, entries);
+        assert!(check(&[("dir", false), ("dir/link", true), ("dir/a.txt", false)]).is_ok());
+        let rejected: [&[(&str, bool)]; 4] = [
+            &[("dir/link", true), ("dir/link/evil.txt", false)],
+            &[("dir\\link", true), ("dir/link", false)],
+            &[("link", true), ("./link/sub/evil.txt", false)],
+            &[("../evil.txt", false)],
+        ];
+        for entries in rejected {
+            assert!(
+                matches!(check(entries), Err(FlateError::UnsafePath(_))),
+                "{entries:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_detect_format() {
+        let test_dir = cd_test_dir("detect_format");
+        let mut tar = vec![0u8; 512];
+        tar[TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + 6].copy_from_slice(b"ustar\0");
+        let cases: [(&str, &[u8], Option<ArchiveFormat>); 10] = [
+            ("no_extension", b"PK\x03\x04rest", Some(ArchiveFormat::Zip)),
+            ("old.rar", b"Rar!\x1A\x07\x00rest", Some(ArchiveFormat::Rar)),
+            (
+                "new.rar",
+                b"Rar!\x1A\x07\x01\x00rest",
+                Some(ArchiveFormat::Rar),
+            ),
+            (
+                "named.zip",
+                b"7z\xBC\xAF\x27\x1Crest",
+                Some(ArchiveFormat::SevenZ),
+            ),
+            ("a.tar.gz", b"\x1F\x8B\x08rest", Some(ArchiveFormat::Gzip)),
+            (
+                "a.tar.zst",
+                b"\x28\xB5\x2F\xFDrest",
+                Some(ArchiveFormat::Zstd),
+            ),
+            ("a.txz", b"\xFD7zXZ\x00rest", Some(ArchiveFormat::Xz)),
+            ("a.tar", &tar, Some(ArchiveFormat::Tar)),
+            ("game.exe", b"MZ", None),
+            ("empty", b"", None),
+        ];
+        for (name, content, format) in cases {
+            let path = test_dir.join(name);
+            fs::write(&path, content).unwrap();
+            assert_eq!(ArchiveFormat::detect(&path).unwrap(), format, "{name}");
+        }
+        assert!(ArchiveFormat::has_archive_extension("a/b.ZIP"));
+        assert!(!ArchiveFormat::has_archive_extension("a/b.exe"));
+        assert!(!ArchiveFormat::has_archive_extension("a/no_extension"));
+
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_pick_7z() {
+        let missing = Path::new("/nonexistent/7z");
+        assert!(
+            pick_7z(SevenZBackend::Auto, missing, None)
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            pick_7z(SevenZBackend::Internal, missing, None)
+                .unwrap()
+                .is_none()
+        );
+        assert!(matches!(
+            pick_7z(SevenZBackend::External, missing, Some("pwd")),
+            Err(FlateError::External(_))
+        ));
+    }
+
+    #[test]
+    fn test_can_pipe_7z_password() {
+        let banner = "7-Zip 23.01 (x64) : Copyright (c) 1999-2023 Igor Pavlov : 2023-06-20";
+        assert!(can_pipe_7z_password(banner, "p@ss \"$(x)\" -y;|&"));
+        assert!(!can_pipe_7z_password(banner, "中文密码"));
+        assert!(!can_pipe_7z_password(banner, "line\nbreak"));
+        assert!(!can_pipe_7z_password(banner, ""));
+        let p7zip = "7-Zip [64] 16.02 : Copyright (c) 1999-2016 Igor Pavlov : 2016-05-21";
+        assert!(!can_pipe_7z_password(
+            &format!("{p7zip}\np7zip Version 16.02"),
+            "p@ss"
+        ));
+    }
+
+    #[test]
+    fn test_7z_progress() {
+        let test_dir = cd_test_dir("7z_progress");
+        let origin_path = test_dir.join("origin");
+        fs::create_dir_all(&origin_path).unwrap();
+        create_test_files(&origin_path).unwrap();
+        let out = test_dir.join("progress.7z");
+
+        let mut reports = Vec::new();
+        let monitor = FlateMonitor::new(CancelToken::new(), |p| reports.push(p.clone()));
+        compress_7z(&origin_path, &out, &ArchiveOptions::default(), &monitor).unwrap();
+        let last = monitor.progress();
+        assert_eq!(last.entries_total, Some(5));
+        assert_eq!(last.entries_done, 5);
+        assert_eq!(last.bytes_total, Some(9 + 17 + 1024 * 26));
+        assert_eq!(last.bytes_done, last.bytes_total.unwrap());
+        drop(monitor);
+        assert!(reports.len() >= 2);
+
+        let monitor = FlateMonitor::silent();
+        decompress_7z(&out, test_dir.join("extracted"), None, &monitor).unwrap();
+        assert_eq!(monitor.progress().entries_done, 5);
+        assert_eq!(monitor.progress().bytes_done, 9 + 17 + 1024 * 26);
+
+        // A cancelled token stops before anything is extracted
+        let monitor = FlateMonitor::silent();
+        monitor.cancel_token().cancel();
+        let result = decompress_7z(&out, test_dir.join("cancelled"), None, &monitor);
+        assert!(matches!(result, Err(FlateError::Cancelled)));
+        assert!(!test_dir.join("cancelled/file1.txt").exists());
+
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_7z_progress() {
+        assert_eq!(
+            parse_7z_progress(" 42% 12 + dir/file.bin"),
+            Some((42, Some(12), Some("dir/file.bin".to_string())))
+        );
+        assert_eq!(
+            parse_7z_progress("  7% - a.txt"),
+            Some((7, None, Some("a.txt".to_string())))
+        );
+        assert_eq!(parse_7z_progress("100%"), Some((100, None, None)));
+        assert_eq!(parse_7z_progress("Everything is Ok"), None);
     }
 }
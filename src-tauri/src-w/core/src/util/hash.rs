@@ -0,0 +1,54 @@
+use crate::util::volume::VolumeSet;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Hash the content of a file with BLAKE3, returning the lowercase hex digest
+pub fn hash_file(path: impl AsRef<Path>) -> Result<String, io::Error> {
+    let mut hasher = blake3::Hasher::new();
+    let mut file = File::open(path)?;
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Hash the contents of files one after another, the volumes of a split archive as a whole.
+/// Same as [hash_file] for a single file
+pub fn hash_files(paths: &[impl AsRef<Path>]) -> Result<String, io::Error> {
+    let mut hasher = blake3::Hasher::new();
+    for path in paths {
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Hash a directory tree with BLAKE3, covering relative paths, sizes and contents of all files
+pub fn hash_dir(path: impl AsRef<Path>) -> Result<String, io::Error> {
+    let mut hasher = blake3::Hasher::new();
+    for entry in walkdir::WalkDir::new(&path).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(&path)
+            .unwrap_or(entry.path())
+            .to_string_lossy()
+            .replace('\\', "/");
+        hasher.update(relative.as_bytes());
+        hasher.update(&[0]);
+        hasher.update(&entry.metadata()?.len().to_le_bytes());
+        io::copy(&mut File::open(entry.path())?, &mut hasher)?;
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Hash a directory tree, or a file with every volume if it is a split archive,
+/// see [hash_dir] and [hash_files]
+pub fn hash_path(path: impl AsRef<Path>) -> Result<String, io::Error> {
+    if path.as_ref().is_dir() {
+        hash_dir(path)
+    } else {
+        hash_files(&VolumeSet::find(path)?.volumes)
+    }
+}
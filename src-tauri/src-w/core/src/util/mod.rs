@@ -1,10 +1,13 @@
+use std::ffi::OsStr;
 use std::process::Command;
 
 pub mod file;
 pub mod flate;
+pub mod hash;
+pub mod volume;
 
 #[cfg(target_os = "windows")]
-pub fn create_hidden_command(cmd: &str) -> Command {
+pub fn create_hidden_command(cmd: impl AsRef<OsStr>) -> Command {
     use std::os::windows::process::CommandExt;
     const CREATE_NO_WINDOW: u32 = 0x08000000;
 
@@ -14,6 +17,6 @@ pub fn create_hidden_command(cmd: &str) -> Command {
 }
 
 #[cfg(not(target_os = "windows"))]
-pub fn create_hidden_command(cmd: &str) -> Command {
+pub fn create_hidden_command(cmd: impl AsRef<OsStr>) -> Command {
     Command::new(cmd)
 }
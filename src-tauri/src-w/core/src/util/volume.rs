@@ -0,0 +1,337 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// How the volumes of a split archive are named
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum VolumeScheme {
+    /// `name.part1.rar`, `name.part2.rar`, ..., numbers may be zero padded
+    RarPart,
+    /// `name.rar` first, then `name.r00`, `name.r01`, ...
+    RarOld,
+    /// `name.7z.001`, `name.zip.002`, ..., a plain byte split of a single archive
+    Numbered,
+    /// `name.z01`, `name.z02`, ... with `name.zip` last, each volume with its own offsets
+    ZipSpanned,
+}
+
+/// A volume file name taken apart
+#[derive(Debug)]
+struct VolumeName {
+    scheme: VolumeScheme,
+    /// The name without the volume part, like `name` of `name.part1.rar`
+    stem: String,
+    /// Position in the set, the last `name.zip` of [VolumeScheme::ZipSpanned] is [u32::MAX]
+    index: u32,
+    /// Digits of the volume number, to name missing volumes alike
+    width: usize,
+}
+
+fn parse_volume_name(name: &str) -> Option<VolumeName> {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    // ASCII only, so byte offsets still match the original name
+    let lower = name.to_ascii_lowercase();
+    let (stem, ext) = lower.rsplit_once('.')?;
+    let volume = |scheme, stem_len: usize, index, width| {
+        Some(VolumeName {
+            scheme,
+            stem: name[..stem_len].to_string(),
+            index,
+            width,
+        })
+    };
+
+    match ext {
+        "rar" => match stem.rsplit_once(".part") {
+            Some((base, number)) if digits(number) => volume(
+                VolumeScheme::RarPart,
+                base.len(),
+                number.parse().ok()?,
+                number.len(),
+            ),
+            _ => volume(VolumeScheme::RarOld, stem.len(), 0, 2),
+        },
+        "zip" => volume(VolumeScheme::ZipSpanned, stem.len(), u32::MAX, 2),
+        _ if ext.len() == 3 && ext.starts_with('r') && digits(&ext[1..]) => volume(
+            VolumeScheme::RarOld,
+            stem.len(),
+            ext[1..].parse::<u32>().ok()? + 1,
+            2,
+        ),
+        _ if ext.len() == 3 && ext.starts_with('z') && digits(&ext[1..]) => volume(
+            VolumeScheme::ZipSpanned,
+            stem.len(),
+            ext[1..].parse().ok()?,
+            2,
+        ),
+        _ if ext.len() >= 3 && digits(ext) && stem.contains('.') => volume(
+            VolumeScheme::Numbered,
+            stem.len(),
+            ext.parse().ok()?,
+            ext.len(),
+        ),
+        _ => None,
+    }
+}
+
+fn volume_name(scheme: VolumeScheme, stem: &str, index: u32, width: usize) -> String {
+    match scheme {
+        VolumeScheme::RarPart => format!("{stem}.part{index:0width$}.rar"),
+        VolumeScheme::RarOld if index == 0 => format!("{stem}.rar"),
+        VolumeScheme::RarOld => format!("{stem}.r{:02}", index - 1),
+        VolumeScheme::Numbered => format!("{stem}.{index:0width$}"),
+        VolumeScheme::ZipSpanned if index == u32::MAX => format!("{stem}.zip"),
+        VolumeScheme::ZipSpanned => format!("{stem}.z{index:02}"),
+    }
+}
+
+/// Whether the file is named like a volume of a split archive, including the first one
+pub fn is_volume_name(path: impl AsRef<Path>) -> bool {
+    path.as_ref()
+        .file_name()
+        .is_some_and(|name| parse_volume_name(&name.to_string_lossy()).is_some())
+}
+
+/// The volumes of an archive file in order, a single one unless it is split, see [VolumeSet::find]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct VolumeSet {
+    /// [None] for an archive that is not split
+    pub scheme: Option<VolumeScheme>,
+    /// The volumes present, in order
+    pub volumes: Vec<PathBuf>,
+    /// Volumes missing between the first and the last one present.
+    /// A missing last volume can't be told from the names, only from the recorded size
+    pub missing: Vec<PathBuf>,
+}
+
+impl VolumeSet {
+    /// Find the sibling volumes of any volume of a split archive
+    pub fn find(path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        let path = path.as_ref();
+        let single = || Self {
+            scheme: None,
+            volumes: vec![path.to_path_buf()],
+            missing: Vec::new(),
+        };
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return Ok(single());
+        };
+        let Some(own) = parse_volume_name(&name.to_string_lossy()) else {
+            return Ok(single());
+        };
+
+        let dir = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        let mut found = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if let Some(other) = parse_volume_name(&entry.file_name().to_string_lossy())
+                && other.scheme == own.scheme
+                && other.stem == own.stem
+                && entry.file_type()?.is_file()
+            {
+                found.push((other.index, parent.join(entry.file_name())));
+            }
+        }
+        found.sort();
+
+        // A lone `name.rar` or `name.zip` is just an archive
+        let is_main = matches!(
+            (own.scheme, own.index),
+            (VolumeScheme::RarOld, 0) | (VolumeScheme::ZipSpanned, u32::MAX)
+        );
+        if found.is_empty() || (is_main && found.len() == 1) {
+            return Ok(single());
+        }
+
+        let first = match own.scheme {
+            VolumeScheme::RarOld => 0,
+            _ => 1,
+        };
+        let last = found
+            .iter()
+            .map(|(index, _)| *index)
+            .filter(|index| *index != u32::MAX)
+            .max()
+            .unwrap_or(first);
+        let mut expected = (first..=last).collect::<Vec<_>>();
+        if own.scheme == VolumeScheme::ZipSpanned {
+            expected.push(u32::MAX);
+        }
+        let missing = expected
+            .into_iter()
+            .filter(|index| !found.iter().any(|(found, _)| found == index))
+            .map(|index| parent.join(volume_name(own.scheme, &own.stem, index, own.width)))
+            .collect();
+
+        Ok(Self {
+            scheme: Some(own.scheme),
+            volumes: found.into_iter().map(|(_, path)| path).collect(),
+            missing,
+        })
+    }
+
+    pub fn is_split(&self) -> bool {
+        self.scheme.is_some()
+    }
+
+    /// The volume the archive starts in, holding its signature
+    pub fn first(&self) -> &Path {
+        &self.volumes[0]
+    }
+
+    /// The volume a backend opens to read the whole set, the last `name.zip` of a spanned zip
+    pub fn entry(&self) -> &Path {
+        match self.scheme {
+            Some(VolumeScheme::ZipSpanned) => self.volumes.last(),
+            _ => self.volumes.first(),
+        }
+        .expect("a volume set is never empty")
+    }
+
+    /// Total size of the volumes present
+    pub fn size(&self) -> Result<u64, io::Error> {
+        self.volumes
+            .iter()
+            .map(|volume| volume.metadata().map(|meta| meta.len()))
+            .sum()
+    }
+
+    /// Read the volumes one after another as a single archive
+    pub fn open(&self) -> Result<VolumeReader, io::Error> {
+        VolumeReader::open(&self.volumes)
+    }
+}
+
+/// Reads the volumes of a byte split archive as one, see [VolumeScheme::Numbered]
+pub struct VolumeReader {
+    /// Each volume with where it starts in the whole
+    volumes: Vec<(File, u64)>,
+    len: u64,
+    pos: u64,
+}
+
+impl VolumeReader {
+    pub fn open(paths: &[PathBuf]) -> Result<Self, io::Error> {
+        let mut volumes = Vec::with_capacity(paths.len());
+        let mut len = 0;
+        for path in paths {
+            let file = File::open(path)?;
+            let size = file.metadata()?.len();
+            volumes.push((file, len));
+            len += size;
+        }
+        Ok(Self {
+            volumes,
+            len,
+            pos: 0,
+        })
+    }
+}
+
+impl Read for VolumeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+        // The last volume starting at or before the position, skipping empty ones
+        let cur = self
+            .volumes
+            .partition_point(|(_, start)| *start <= self.pos)
+            - 1;
+        let end = self
+            .volumes
+            .get(cur + 1)
+            .map_or(self.len, |(_, start)| *start);
+        let max = (end - self.pos).min(buf.len() as u64) as usize;
+        let (file, start) = &mut self.volumes[cur];
+        file.seek(SeekFrom::Start(self.pos - *start))?;
+        let read = file.read(&mut buf[..max])?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for VolumeReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        match target {
+            Some(target) => {
+                self.pos = target;
+                Ok(target)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seeking before the start of the volumes",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::file::cd_test_dir;
+
+    #[test]
+    fn test_find_volumes() {
+        let dir = cd_test_dir("volumes");
+        for name in [
+            "game.part01.rar",
+            "game.part03.rar",
+            "old.rar",
+            "old.r00",
+            "old.r01",
+            "data.7z.001",
+            "data.7z.002",
+            "data.7z.003",
+            "span.z01",
+            "span.z02",
+            "lone.zip",
+        ] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+
+        let set = VolumeSet::find(dir.join("game.part03.rar")).unwrap();
+        assert_eq!(set.scheme, Some(VolumeScheme::RarPart));
+        assert_eq!(set.first(), dir.join("game.part01.rar"));
+        assert_eq!(set.missing, vec![dir.join("game.part02.rar")]);
+
+        let set = VolumeSet::find(dir.join("old.rar")).unwrap();
+        assert_eq!(
+            set.volumes,
+            ["old.rar", "old.r00", "old.r01"].map(|name| dir.join(name))
+        );
+        assert!(set.missing.is_empty());
+
+        let set = VolumeSet::find(dir.join("span.z02")).unwrap();
+        assert_eq!(set.entry(), dir.join("span.z02"));
+        assert_eq!(set.missing, vec![dir.join("span.zip")]);
+
+        let set = VolumeSet::find(dir.join("lone.zip")).unwrap();
+        assert!(!set.is_split());
+        assert!(!is_volume_name(dir.join("tool.exe")));
+
+        let set = VolumeSet::find(dir.join("data.7z.001")).unwrap();
+        assert_eq!(set.volumes.len(), 3);
+        assert_eq!(set.size().unwrap(), 33);
+        let mut reader = set.open().unwrap();
+        let mut all = String::new();
+        reader.read_to_string(&mut all).unwrap();
+        assert_eq!(all, "data.7z.001data.7z.002data.7z.003");
+        reader.seek(SeekFrom::Start(9)).unwrap();
+        let mut buf = [0u8; 6];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"01data");
+        assert_eq!(reader.seek(SeekFrom::End(-3)).unwrap(), 30);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
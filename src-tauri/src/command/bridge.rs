@@ -14,9 +14,9 @@ pub enum PlatformLimited {
     DLSite,
 }
 
-impl Into<Platform> for PlatformLimited {
-    fn into(self) -> Platform {
-        match self {
+impl From<PlatformLimited> for Platform {
+    fn from(platform: PlatformLimited) -> Self {
+        match platform {
             PlatformLimited::Unknown => Platform::Unknown,
             PlatformLimited::Steam => Platform::Steam,
             PlatformLimited::DLSite => Platform::DLSite,
@@ -1,13 +1,65 @@
 mod bridge;
+mod task;
 
 use crate::command::bridge::PlatformInfo;
+use crate::command::task::{TaskGuard, TaskProgress, task_register};
+use m_core::data::deploy::{DeployMethod, DeployOffMode, DeployOffReport};
+use m_core::data::duplicate::{Duplicate, DuplicatePolicy};
+use m_core::data::job::{
+    Job, JobEvent, JobKind, job_cancel as core_job_cancel, job_clear as core_job_clear, job_hold,
+    job_list, job_listen, job_submit,
+};
 use m_core::data::library::{
-    Library, lib_add, lib_del, lib_delegate_create, lib_delegate_deploy, lib_delegate_deploy_off,
-    lib_export, lib_get_all, lib_import,
+    DriftReport, Library, VerifyReport, lib_add, lib_add_screenshot, lib_add_with,
+    lib_archive_contents, lib_artwork, lib_check_deployments, lib_comic_page, lib_comic_scan,
+    lib_del, lib_delegate_create, lib_delegate_deploy, lib_delegate_deploy_off, lib_export,
+    lib_find_duplicates, lib_get_all, lib_import, lib_query, lib_remove_artwork, lib_set_cover,
+    lib_set_passphrase, lib_verify,
 };
 use m_core::data::metadata::Metadata;
-use tauri::command;
-use tracing::error;
+use m_core::data::query::{LibraryPage, LibraryQuery};
+use m_core::foundation::secret;
+use m_core::util::flate::{ArchiveEntry, ArchiveOptions, FlateMonitor};
+use std::fs;
+use tauri::ipc::Response;
+use tauri::{AppHandle, Emitter, command};
+use tracing::{error, warn};
+
+const EVENT_TASK_PROGRESS: &str = "task-progress";
+const EVENT_JOB_STATE: &str = "job-state";
+const EVENT_JOB_PROGRESS: &str = "job-progress";
+
+/// Monitor emitting the progress of a task to the frontend
+fn task_monitor<'a>(app: &'a AppHandle, guard: &'a TaskGuard) -> FlateMonitor<'a> {
+    FlateMonitor::new(guard.token(), move |progress| {
+        let payload = TaskProgress {
+            task: guard.key(),
+            progress,
+        };
+        if let Err(err) = app.emit(EVENT_TASK_PROGRESS, payload) {
+            warn!("Failed to emit task progress: {err}");
+        }
+    })
+}
+
+/// Forward state changes and progress of background jobs to the frontend
+pub fn job_events(app: AppHandle) {
+    job_listen(move |event| {
+        let result = match event {
+            JobEvent::State(job) => app.emit(EVENT_JOB_STATE, job),
+            JobEvent::Progress { job, progress } => app.emit(
+                EVENT_JOB_PROGRESS,
+                TaskProgress {
+                    task: job,
+                    progress,
+                },
+            ),
+        };
+        if let Err(err) = result {
+            warn!("Failed to emit job event: {err}");
+        }
+    });
+}
 
 fn internal_library_add(data: Metadata) -> Result<(), String> {
     lib_add(data).map_err(|err| {
@@ -26,6 +78,15 @@ pub fn library_get() -> Result<Library, String> {
     })
 }
 
+#[command]
+pub fn library_query(query: LibraryQuery) -> Result<LibraryPage, String> {
+    lib_query(&query).map_err(|err| {
+        let err_msg = format!("Failed to query library: {err}");
+        error!(err_msg);
+        err_msg
+    })
+}
+
 #[command]
 pub fn library_set(data: Metadata) -> Result<(), String> {
     internal_library_add(data)
@@ -50,41 +111,287 @@ pub fn library_import() -> Result<bool, String> {
     lib_import().map_err(|err| err.to_string())
 }
 
-#[command]
-pub fn library_deploy(id: String, path: String) -> Result<(), String> {
-    lib_delegate_deploy(id.as_str(), path.as_str()).map_err(|err| {
+#[command(async)]
+pub fn library_verify(id: String) -> Result<VerifyReport, String> {
+    let _hold = job_hold(&id).map_err(|err| err.to_string())?;
+    lib_verify(id.as_str()).map_err(|err| {
+        let err_msg = format!("Failed to verify metadata: {err}");
+        error!(err_msg);
+        err_msg
+    })
+}
+
+#[command(async)]
+pub fn library_deploy(
+    app: AppHandle,
+    id: String,
+    path: String,
+    method: Option<DeployMethod>,
+) -> Result<(), String> {
+    let guard = task_register(&id)?;
+    lib_delegate_deploy(
+        id.as_str(),
+        path.as_str(),
+        method.unwrap_or_default(),
+        &task_monitor(&app, &guard),
+    )
+    .map_err(|err| {
         let err_msg = format!("Failed to deploy metadata: {err}");
         error!(err_msg);
         err_msg
     })
 }
 
+#[command(async)]
+pub fn library_deploy_off(
+    id: String,
+    deployment: Option<String>,
+    mode: Option<DeployOffMode>,
+) -> Result<Vec<DeployOffReport>, String> {
+    let _hold = job_hold(&id).map_err(|err| err.to_string())?;
+    lib_delegate_deploy_off(id.as_str(), deployment.as_deref(), mode.unwrap_or_default()).map_err(
+        |err| {
+            let err_msg = format!("Failed to off deploy data: {err}");
+            error!(err_msg);
+            err_msg
+        },
+    )
+}
+
+#[command(async)]
+pub fn library_check_deployments() -> Result<Vec<DriftReport>, String> {
+    lib_check_deployments().map_err(|err| {
+        let err_msg = format!("Failed to check deployments: {err}");
+        error!(err_msg);
+        err_msg
+    })
+}
+
+#[command(async)]
+pub fn library_archive_contents(id: String) -> Result<Vec<ArchiveEntry>, String> {
+    lib_archive_contents(id.as_str()).map_err(|err| {
+        let err_msg = format!("Failed to list archive contents: {err}");
+        error!(err_msg);
+        err_msg
+    })
+}
+
+/// Raw bytes of the page, the frontend wraps them in a blob of the page's image type
+#[command(async)]
+pub fn library_comic_page(id: String, index: usize) -> Result<Response, String> {
+    lib_comic_page(id.as_str(), index)
+        .map(|page| Response::new(page.data))
+        .map_err(|err| {
+            let err_msg = format!("Failed to read comic page: {err}");
+            error!(err_msg);
+            err_msg
+        })
+}
+
+#[command(async)]
+pub fn library_comic_scan(id: String) -> Result<Metadata, String> {
+    lib_comic_scan(id.as_str()).map_err(|err| {
+        let err_msg = format!("Failed to scan comic pages: {err}");
+        error!(err_msg);
+        err_msg
+    })
+}
+
+#[command(async)]
+pub fn library_set_cover(id: String, path: String) -> Result<Metadata, String> {
+    let data = fs::read(&path).map_err(|err| format!("Failed to read image {path}: {err}"))?;
+    lib_set_cover(id.as_str(), &data).map_err(|err| {
+        let err_msg = format!("Failed to set cover: {err}");
+        error!(err_msg);
+        err_msg
+    })
+}
+
+#[command(async)]
+pub fn library_add_screenshot(id: String, path: String) -> Result<Metadata, String> {
+    let data = fs::read(&path).map_err(|err| format!("Failed to read image {path}: {err}"))?;
+    lib_add_screenshot(id.as_str(), &data).map_err(|err| {
+        let err_msg = format!("Failed to add screenshot: {err}");
+        error!(err_msg);
+        err_msg
+    })
+}
+
 #[command]
-pub fn library_deploy_off(id: String) -> Result<(), String> {
-    lib_delegate_deploy_off(id.as_str()).map_err(|err| {
-        let err_msg = format!("Failed to off deploy data: {err}");
+pub fn library_remove_artwork(id: String, hash: String) -> Result<Metadata, String> {
+    lib_remove_artwork(id.as_str(), hash.as_str()).map_err(|err| {
+        let err_msg = format!("Failed to remove image: {err}");
         error!(err_msg);
         err_msg
     })
 }
 
+/// Raw bytes of the image or of its JPEG thumbnail
+#[command(async)]
+pub fn library_artwork(id: String, hash: String, thumbnail: bool) -> Result<Response, String> {
+    lib_artwork(id.as_str(), hash.as_str(), thumbnail)
+        .map(|(_, data)| Response::new(data))
+        .map_err(|err| {
+            let err_msg = format!("Failed to read image: {err}");
+            error!(err_msg);
+            err_msg
+        })
+}
+
 #[command]
-pub fn metadata_add(title: String, archive_path: String, info: PlatformInfo) -> Result<(), String> {
+pub fn secret_is_locked() -> Result<bool, String> {
+    secret::secret_is_locked().map_err(|err| err.to_string())
+}
+
+#[command]
+pub fn secret_unlock(passphrase: String) -> Result<(), String> {
+    secret::secret_unlock(&passphrase).map_err(|err| {
+        let err_msg = format!("Failed to unlock secrets: {err}");
+        error!(err_msg);
+        err_msg
+    })
+}
+
+/// Encrypt archive passwords with a new master passphrase, or with a key file without one
+#[command(async)]
+pub fn secret_set_passphrase(passphrase: Option<String>) -> Result<usize, String> {
+    lib_set_passphrase(passphrase.as_deref()).map_err(|err| {
+        let err_msg = format!("Failed to set master passphrase: {err}");
+        error!(err_msg);
+        err_msg
+    })
+}
+
+#[command(async)]
+pub fn metadata_duplicates(
+    archive_path: Option<String>,
+    info: PlatformInfo,
+) -> Result<Vec<Duplicate>, String> {
+    let mut metadata = Metadata::builder()
+        .title(String::new())
+        .platform(info.name.into())
+        .maybe_platform_id(info.id)
+        .maybe_archive_path(archive_path)
+        .build();
+    lib_find_duplicates(&mut metadata).map_err(|err| {
+        let err_msg = format!("Failed to check duplicates: {err}");
+        error!(err_msg);
+        err_msg
+    })
+}
+
+#[command(async)]
+pub fn metadata_add(
+    title: String,
+    archive_path: String,
+    info: PlatformInfo,
+    policy: Option<DuplicatePolicy>,
+) -> Result<String, String> {
     let mut metadata = Metadata::new(title, info.name.into(), info.id, archive_path);
     let _ = metadata.calculate_size();
-    internal_library_add(metadata)
+    lib_add_with(metadata, policy.unwrap_or_default()).map_err(|err| {
+        let err_msg = format!("Failed to add metadata: {err}");
+        error!(err_msg);
+        err_msg
+    })
 }
 
-#[command]
+#[command(async)]
 pub fn metadata_create(
+    app: AppHandle,
     title: String,
     from_path: String,
     info: PlatformInfo,
-    password: Option<String>,
-) -> Result<(), String> {
-    lib_delegate_create(title, info.name.into(), info.id, from_path, password).map_err(|err| {
+    options: Option<ArchiveOptions>,
+    policy: Option<DuplicatePolicy>,
+) -> Result<String, String> {
+    let guard = task_register(&from_path)?;
+    let monitor = task_monitor(&app, &guard);
+    lib_delegate_create(
+        title,
+        info.name.into(),
+        info.id,
+        from_path,
+        options.unwrap_or_default(),
+        policy.unwrap_or_default(),
+        &monitor,
+    )
+    .map_err(|err| {
         let err_msg = format!("Failed to create archive: {err}");
         error!(err_msg);
         err_msg
     })
 }
+
+/// Cancel a running deploy by entry id, or a running creation by source path
+#[command]
+pub fn task_cancel(task: String) -> bool {
+    task::task_cancel(&task)
+}
+
+#[command]
+pub fn job_deploy(id: String, path: String, method: Option<DeployMethod>) -> String {
+    job_submit(JobKind::Deploy {
+        id,
+        path,
+        method: method.unwrap_or_default(),
+    })
+}
+
+#[command]
+pub fn job_deploy_off(
+    id: String,
+    deployment: Option<String>,
+    mode: Option<DeployOffMode>,
+) -> String {
+    job_submit(JobKind::DeployOff {
+        id,
+        deployment,
+        mode: mode.unwrap_or_default(),
+    })
+}
+
+#[command]
+pub fn job_create(
+    title: String,
+    from_path: String,
+    info: PlatformInfo,
+    options: Option<ArchiveOptions>,
+    policy: Option<DuplicatePolicy>,
+) -> String {
+    job_submit(JobKind::Create {
+        title,
+        platform: info.name.into(),
+        platform_id: info.id,
+        from_path,
+        options: options.unwrap_or_default(),
+        policy: policy.unwrap_or_default(),
+    })
+}
+
+/// Verify a single entry, or the whole library without an id
+#[command]
+pub fn job_verify(id: Option<String>) -> String {
+    job_submit(JobKind::Verify { id })
+}
+
+#[command]
+pub fn job_repair(id: String, deployment: String) -> String {
+    job_submit(JobKind::Repair { id, deployment })
+}
+
+#[command]
+pub fn job_get_all() -> Vec<Job> {
+    job_list()
+}
+
+#[command]
+pub fn job_cancel(id: String) -> bool {
+    core_job_cancel(&id)
+}
+
+/// Forget finished jobs
+#[command]
+pub fn job_clear() -> usize {
+    core_job_clear()
+}
@@ -0,0 +1,66 @@
+use m_core::data::job::{JobHold, job_hold};
+use m_core::util::flate::{CancelToken, FlateProgress};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+/// Cancel tokens of running tasks, keyed by the entry id or the source path
+static TASKS: LazyLock<Mutex<HashMap<String, CancelToken>>> = LazyLock::new(Default::default);
+
+/// Payload of the progress event of a task
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskProgress<'a> {
+    pub task: &'a str,
+    pub progress: &'a FlateProgress,
+}
+
+/// Keeps a task cancellable and its key held from the job queue until dropped
+pub struct TaskGuard {
+    key: String,
+    token: CancelToken,
+    _hold: JobHold,
+}
+
+impl TaskGuard {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn token(&self) -> CancelToken {
+        self.token.clone()
+    }
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        if let Ok(mut tasks) = TASKS.lock() {
+            tasks.remove(&self.key);
+        }
+    }
+}
+
+/// Register a task, fails if a task or a running job with the same key exists
+pub fn task_register(key: &str) -> Result<TaskGuard, String> {
+    let hold = job_hold(key).map_err(|err| err.to_string())?;
+    let mut tasks = TASKS
+        .lock()
+        .map_err(|_| "Failed to acquire lock".to_string())?;
+    let token = CancelToken::new();
+    tasks.insert(key.to_string(), token.clone());
+    Ok(TaskGuard {
+        key: key.to_string(),
+        token,
+        _hold: hold,
+    })
+}
+
+/// Cancel a running task, returns false if there is none with the key
+pub fn task_cancel(key: &str) -> bool {
+    match TASKS.lock().ok().and_then(|tasks| tasks.get(key).cloned()) {
+        Some(token) => {
+            token.cancel();
+            true
+        }
+        None => false,
+    }
+}
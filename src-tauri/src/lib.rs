@@ -11,16 +11,43 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_opener::init())
+        .setup(|app| {
+            job_events(app.handle().clone());
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             library_get,
+            library_query,
             library_del,
             library_set,
+            library_verify,
             library_deploy,
             library_deploy_off,
+            library_check_deployments,
+            library_archive_contents,
+            library_comic_scan,
+            library_comic_page,
+            library_set_cover,
+            library_add_screenshot,
+            library_remove_artwork,
+            library_artwork,
             library_export,
             library_import,
+            secret_is_locked,
+            secret_unlock,
+            secret_set_passphrase,
+            metadata_duplicates,
             metadata_add,
-            metadata_create
+            metadata_create,
+            task_cancel,
+            job_deploy,
+            job_deploy_off,
+            job_create,
+            job_verify,
+            job_repair,
+            job_get_all,
+            job_cancel,
+            job_clear
         ])
         .run(tauri::generate_context!())
         .expect("Initialization failed");
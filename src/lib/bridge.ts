@@ -39,6 +39,23 @@ export type Tag = {
   category?: string;
 };
 
+/** What wrote an archive, the external 7z with the first line of its banner */
+export type ArchiveBackend = { External7z: string } | "Internal7z" | "Zip" | "Tar";
+
+/** Pages of a comic, `cover` is the path of the first page in the archive */
+export type ComicInfo = {
+  page_count: number;
+  cover: string;
+};
+
+/** An image stored for an entry, named by the BLAKE3 digest of its content */
+export type Artwork = {
+  hash: string;
+  ext: string;
+  width: number;
+  height: number;
+};
+
 export type Metadata = {
   id: string;
   title: string;
@@ -55,8 +72,16 @@ export type Metadata = {
 
   archive_path?: string;
   archive_password?: string;
-  deployed_path?: string;
+  /** Only for archives created by the app */
+  archive_backend?: ArchiveBackend;
+  deployments?: Deployment[];
   size_bytes?: number;
+  archive_hash?: string;
+
+  /** Only for comics */
+  comic?: ComicInfo;
+  cover?: Artwork;
+  screenshots?: Artwork[];
 
   tags?: Tag[];
 
@@ -64,6 +89,185 @@ export type Metadata = {
   date_updated?: string;
 };
 
+export type DeployType = "Directory" | "CopyFile" | "Symlink" | "Hardlink" | "Reflink";
+
+export type Deployment = {
+  id: string;
+  path: string;
+  deploy_type: DeployType;
+  date_deployed: string;
+  manifest?: DeployManifest;
+};
+
+export type DeployManifest = {
+  files: { path: string; size: number; hash: string; link?: string }[];
+};
+
+export type DeployMethod = "Copy" | "Symlink" | "Hardlink" | "Reflink";
+
+export type DeployOffMode = "RemoveDeployed" | "KeepUserFiles";
+
+export type DeployDrift = {
+  missing: string[];
+  modified: string[];
+};
+
+export type DriftStatus =
+  | "Intact"
+  | "Missing"
+  | { Partial: DeployDrift }
+  | { Modified: DeployDrift }
+  | "Unchecked"
+  | { Unreadable: string };
+
+export type DriftReport = {
+  id: string;
+  title: string;
+  deployment: string;
+  path: string;
+  status: DriftStatus;
+};
+
+export type DeployOffReport = {
+  deployment: string;
+  path: string;
+  removed: string[];
+  modified: string[];
+  added: string[];
+  missing: string[];
+};
+
+export type ArchiveEntry = {
+  path: string;
+  size: number;
+  compressed_size?: number;
+  modified?: string;
+  encrypted: boolean;
+};
+
 export type Library = {
   entries: Metadata[];
 };
+
+export type LibraryFilter = {
+  title?: string;
+  platform?: Platform;
+  content_type?: ContentType;
+  tag_name?: string;
+  tag_category?: string;
+  deployed?: boolean;
+  size_min?: number;
+  size_max?: number;
+  created_after?: string;
+  created_before?: string;
+  updated_after?: string;
+  updated_before?: string;
+};
+
+export type LibrarySort = {
+  field: "Title" | "Platform" | "Size" | "DateCreated" | "DateUpdated";
+  order: "Asc" | "Desc";
+};
+
+export type LibraryQuery = {
+  filter?: LibraryFilter;
+  sort?: LibrarySort;
+  offset?: number;
+  limit?: number;
+};
+
+export type LibraryPage = {
+  entries: Metadata[];
+  total: number;
+  offset: number;
+  limit?: number;
+};
+
+export type VerifyStatus =
+  | "Intact"
+  | "NoArchive"
+  | "Missing"
+  | "UnknownFormat"
+  | { Unhashed: string }
+  | { Unreadable: string }
+  | { MissingVolumes: string[] }
+  | { Resized: { expected: number; actual: number } }
+  | { Corrupted: { expected: string; actual: string } };
+
+export type VerifyReport = {
+  id: string;
+  title: string;
+  archive_path?: string;
+  status: VerifyStatus;
+};
+
+export type FlateProgress = {
+  bytes_done: number;
+  bytes_total?: number;
+  entries_done: number;
+  entries_total?: number;
+  current?: string;
+};
+
+export type TaskProgress = {
+  task: string;
+  progress: FlateProgress;
+};
+
+export type DuplicatePolicy = "Reject" | "Force" | "Merge";
+
+export type CompressFormat = "SevenZ" | "Zip" | "Tar" | "TarGz" | "TarZst" | "TarXz";
+
+/** Missing fields take their default, a 7z at level 9 in a solid block */
+export type ArchiveOptions = {
+  format?: CompressFormat;
+  /** From 0 to store only up to 9 */
+  level?: number;
+  /** 7z only */
+  solid?: boolean;
+  /** 7z and zip only, never sent back */
+  password?: string;
+  /** 7z only, needs a password */
+  encrypt_header?: boolean;
+};
+
+export type Duplicate = {
+  id: string;
+  title: string;
+  reasons: ("PlatformId" | "ArchivePath" | "ContentHash")[];
+};
+
+export type JobKind =
+  | { Deploy: { id: string; path: string; method: DeployMethod } }
+  | { DeployOff: { id: string; deployment?: string; mode: DeployOffMode } }
+  | {
+      Create: {
+        title: string;
+        platform: Platform;
+        platform_id?: string;
+        from_path: string;
+        options: ArchiveOptions;
+        policy: DuplicatePolicy;
+      };
+    }
+  | { Verify: { id?: string } }
+  | { Repair: { id: string; deployment: string } };
+
+export type JobState = "Queued" | "Running" | "Succeeded" | "Cancelled" | { Failed: string };
+
+export type JobOutput =
+  | { Entry: string }
+  | { DeployOff: DeployOffReport[] }
+  | { Reports: VerifyReport[] }
+  | { Repair: DriftReport };
+
+export type Job = {
+  id: string;
+  kind: JobKind;
+  state: JobState;
+  progress?: FlateProgress;
+  output?: JobOutput;
+  date_created: string;
+  date_started?: string;
+  date_finished?: string;
+};
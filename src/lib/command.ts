@@ -1,4 +1,17 @@
-import type { Library, Metadata } from "@/lib/bridge.ts";
+import type {
+  ArchiveEntry,
+  DeployMethod,
+  DeployOffMode,
+  DeployOffReport,
+  DriftReport,
+  Duplicate,
+  Job,
+  Library,
+  LibraryPage,
+  LibraryQuery,
+  Metadata,
+  VerifyReport,
+} from "@/lib/bridge.ts";
 import type {
   MetadataCreation,
   MetadataSubmit,
@@ -8,23 +21,113 @@ import { invoke } from "@tauri-apps/api/core";
 
 export const command_library_get = async (): Promise<Library> => await invoke("library_get");
 
+export const command_library_query = async (query: LibraryQuery): Promise<LibraryPage> =>
+  await invoke("library_query", { query });
+
 export const command_library_set = async (data: Metadata) => await invoke("library_set", { data });
 
 export const command_library_del = async (id: string): Promise<boolean> =>
   invoke("library_del", { id });
 
-export const command_library_deploy = async (id: string, path: string) =>
-  await invoke("library_deploy", { id, path });
+/** Emits `task-progress` with a `TaskProgress` keyed by the entry id */
+export const command_library_deploy = async (id: string, path: string, method?: DeployMethod) =>
+  await invoke("library_deploy", { id, path, method });
+
+export const command_library_deploy_off = async (
+  id: string,
+  deployment?: string,
+  mode?: DeployOffMode,
+): Promise<DeployOffReport[]> => await invoke("library_deploy_off", { id, deployment, mode });
+
+export const command_library_verify = async (id: string): Promise<VerifyReport> =>
+  await invoke("library_verify", { id });
+
+export const command_library_check_deployments = async (): Promise<DriftReport[]> =>
+  await invoke("library_check_deployments");
+
+export const command_library_archive_contents = async (id: string): Promise<ArchiveEntry[]> =>
+  await invoke("library_archive_contents", { id });
+
+/** Raw bytes of the page, counted from 0 in reading order */
+export const command_library_comic_page = async (id: string, index: number): Promise<ArrayBuffer> =>
+  await invoke("library_comic_page", { id, index });
+
+export const command_library_comic_scan = async (id: string): Promise<Metadata> =>
+  await invoke("library_comic_scan", { id });
+
+export const command_library_set_cover = async (id: string, path: string): Promise<Metadata> =>
+  await invoke("library_set_cover", { id, path });
+
+export const command_library_add_screenshot = async (id: string, path: string): Promise<Metadata> =>
+  await invoke("library_add_screenshot", { id, path });
+
+export const command_library_remove_artwork = async (id: string, hash: string): Promise<Metadata> =>
+  await invoke("library_remove_artwork", { id, hash });
+
+/** Raw bytes of the image, or of its JPEG thumbnail */
+export const command_library_artwork = async (
+  id: string,
+  hash: string,
+  thumbnail: boolean,
+): Promise<ArrayBuffer> => await invoke("library_artwork", { id, hash, thumbnail });
 
-export const command_library_deploy_off = async (id: string) =>
-  await invoke("library_deploy_off", { id });
+export const command_secret_is_locked = async (): Promise<boolean> =>
+  await invoke("secret_is_locked");
+
+export const command_secret_unlock = async (passphrase: string) =>
+  await invoke("secret_unlock", { passphrase });
+
+export const command_secret_set_passphrase = async (passphrase?: string): Promise<number> =>
+  await invoke("secret_set_passphrase", { passphrase });
 
 export const command_library_export = async () => await invoke("library_export");
 
 export const command_library_import = async (): Promise<boolean> => await invoke("library_import");
 
-export const command_metadata_add = async (data: MetadataSubmit) =>
+export const command_metadata_duplicates = async (
+  info: PlatformInfo,
+  archivePath?: string,
+): Promise<Duplicate[]> => await invoke("metadata_duplicates", { info, archivePath });
+
+export const command_metadata_add = async (data: MetadataSubmit): Promise<string> =>
   await invoke("metadata_add", data);
 
-export const command_metadata_create = async (data: MetadataCreation) =>
+/** Emits `task-progress` with a `TaskProgress` keyed by the source path */
+export const command_metadata_create = async (data: MetadataCreation): Promise<string> =>
   await invoke("metadata_create", data);
+
+/** Cancel a running deploy by entry id, or a running creation by source path */
+export const command_task_cancel = async (task: string): Promise<boolean> =>
+  await invoke("task_cancel", { task });
+
+/**
+ * Queues a background job and returns its id. Jobs emit `job-state` with a `Job` on every state
+ * change, and `job-progress` with a `TaskProgress` keyed by the job id
+ */
+export const command_job_deploy = async (
+  id: string,
+  path: string,
+  method?: DeployMethod,
+): Promise<string> => await invoke("job_deploy", { id, path, method });
+
+export const command_job_deploy_off = async (
+  id: string,
+  deployment?: string,
+  mode?: DeployOffMode,
+): Promise<string> => await invoke("job_deploy_off", { id, deployment, mode });
+
+export const command_job_create = async (data: MetadataCreation): Promise<string> =>
+  await invoke("job_create", data);
+
+export const command_job_verify = async (id?: string): Promise<string> =>
+  await invoke("job_verify", { id });
+
+export const command_job_repair = async (id: string, deployment: string): Promise<string> =>
+  await invoke("job_repair", { id, deployment });
+
+export const command_job_get_all = async (): Promise<Job[]> => await invoke("job_get_all");
+
+export const command_job_cancel = async (id: string): Promise<boolean> =>
+  await invoke("job_cancel", { id });
+
+export const command_job_clear = async (): Promise<number> => await invoke("job_clear");
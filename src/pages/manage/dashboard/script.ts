@@ -1,4 +1,9 @@
-import { type Metadata, PlatformType } from "@/lib/bridge.ts";
+import {
+  type ArchiveOptions,
+  type DuplicatePolicy,
+  type Metadata,
+  PlatformType,
+} from "@/lib/bridge.ts";
 import type { QTableColumn } from "quasar";
 
 export type PlatformInfo = {
@@ -10,17 +15,19 @@ export type MetadataSubmit = {
   title: string;
   archivePath: string;
   info: PlatformInfo;
+  policy?: DuplicatePolicy;
 };
 
 export type MetadataCreation = {
   title: string;
   fromPath: string;
   info: PlatformInfo;
-  password?: string;
+  options?: ArchiveOptions;
+  policy?: DuplicatePolicy;
 };
 
 export const metadataDeployed = (metadata: Metadata | null) => {
-  return metadata != null && metadata.deployed_path != null && metadata.deployed_path.length > 0;
+  return metadata != null && metadata.deployments != null && metadata.deployments.length > 0;
 };
 
 export const extractFilenameFromPath = (path: string, fileMode: boolean): string => {
@@ -128,9 +135,9 @@ export const dashboardColumns: QTableColumn[] = [
     field: "archive_path",
   },
   {
-    name: "deployed_path",
+    name: "deployments",
     label: "部署路径",
-    field: "deployed_path",
+    field: (row: Metadata) => row.deployments?.map((d) => d.path).join(", ") || "",
   },
   {
     name: "tags",